@@ -1,9 +1,13 @@
 #[macro_use] extern crate rocket;
 
 use std::fs::read_dir;
+use neural_network_lib::ensemble::{Combination, Ensemble};
+use neural_network_lib::image::{HEIGHT, WIDTH};
 use neural_network_lib::network_interface;
-use neural_network_lib::neural_network::NeuralNetwork;
+use neural_network_lib::saliency::{saliency_map, SaliencyMethod};
 use rocket::fs::FileServer;
+use rocket::http::ContentType;
+use rocket::response::status::BadRequest;
 use rocket::serde::Deserialize;
 use rocket::serde::json::Json;
 use rocket::State;
@@ -16,21 +20,66 @@ struct TrainingData {
 }
 
 #[post("/predict", data = "<body>")]
-fn predict(body: &str, neural_network: &State<NeuralNetwork>) -> String {
-    let input: Vec<f32> = body[1..(body.len() - 1)]
+fn predict(body: &str, neural_network: &State<Ensemble>) -> Result<String, BadRequest<String>> {
+    let input: Vec<f32> = body.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']'))
+        .ok_or(BadRequest("the body must be an array of numbers".to_string()))?
         .split(",")
-        .map(|x| x.parse::<f32>().unwrap())
-        .collect();
+        .map(|x| x.trim().parse::<f32>().map_err(|_| BadRequest(format!("not a number: {x}"))))
+        .collect::<Result<_, _>>()?;
+    check_image(&input)?;
     let result = neural_network.process(&input);
-    format!("[{}]", result.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(","))
+    Ok(format!("[{}]", result.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",")))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct ExplainData {
+    // explain the predicted digit if not given
+    digit: Option<u8>,
+    image_data: Vec<f32>,
+}
+
+// heatmap of the pixels that drove the prediction, transparent where the pixels didn't matter
+#[post("/explain", data = "<data>")]
+fn explain(data: Json<ExplainData>, neural_network: &State<Ensemble>) -> Result<(ContentType, Vec<u8>), BadRequest<String>> {
+    check_image(&data.image_data)?;
+    let digit = match data.digit {
+        Some(digit) => check_digit(digit)?,
+        None => argmax(&neural_network.process(&data.image_data)),
+    };
+    let gradient = neural_network.input_gradient(&data.image_data, digit);
+    let map = saliency_map(&gradient, &data.image_data, SaliencyMethod::GradientTimesInput);
+    Ok((ContentType::PNG, neural_network_lib::image::heatmap_png(&map)))
+}
+
+fn check_digit(digit: u8) -> Result<usize, BadRequest<String>> {
+    if digit < 10 {
+        Ok(digit as usize)
+    } else {
+        Err(BadRequest(format!("digit must be 0 to 9, got {digit}")))
+    }
+}
+
+fn check_image(image_data: &[f32]) -> Result<(), BadRequest<String>> {
+    if image_data.len() == WIDTH * HEIGHT {
+        Ok(())
+    } else {
+        Err(BadRequest(format!("image_data must have {} values, got {}", WIDTH * HEIGHT, image_data.len())))
+    }
+}
+
+fn argmax(output: &[f32]) -> usize {
+    (0..output.len()).fold(0, |best, i| if output[i] > output[best] { i } else { best })
 }
 
 #[post("/training", data = "<data>")]
-fn training(data: Json<TrainingData>) -> String {
+fn training(data: Json<TrainingData>) -> Result<String, BadRequest<String>> {
+    let digit = check_digit(data.digit)?;
+    check_image(&data.image_data)?;
     let old_count = 10773;
     let digit_count = count_files();
-    neural_network_lib::image::save_training_data("training_data", data.digit, &data.image_data, digit_count[data.digit as usize] + old_count);
-    format!("{}", digit_count.iter().sum::<u32>() + 1)
+    neural_network_lib::image::save_training_data("training_data", data.digit, &data.image_data, digit_count[digit] + old_count);
+    Ok(format!("{}", digit_count.iter().sum::<u32>() + 1))
 }
 
 #[get("/count")]
@@ -43,14 +92,32 @@ fn count_files() -> Vec<u32> {
         .collect()
 }
 
+// every listed network is loaded and their outputs are averaged,
+// for example ["networks/3", "networks/after_learn_network", "networks/manual_dataset_5min"]
+const NETWORKS: &[&str] = &["networks/manual_dataset_5min"];
+
 #[launch]
 fn rocket() -> _ {
-    // let neural_network = network_interface::load("networks/3");
-    // let neural_network = network_interface::load("../project/networks/after_learn_network");
-    let neural_network = network_interface::load("networks/manual_dataset_5min");
+    let mut members = Vec::new();
+    for file in NETWORKS {
+        match network_interface::try_load_strict(file) {
+            Ok(network) => members.push(network),
+            Err(e) => {
+                eprintln!("can't load network {file}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let neural_network = match Ensemble::new(members, Combination::Mean) {
+        Ok(ensemble) => ensemble,
+        Err(e) => {
+            eprintln!("can't combine the networks: {e}");
+            std::process::exit(1);
+        }
+    };
 
     rocket::build()
         .manage(neural_network)
         .mount("/", FileServer::from("static")) // serve the index.html on / GET
-        .mount("/", routes![predict, training, count]) // handle HTTP endpoints
+        .mount("/", routes![predict, explain, training, count]) // handle HTTP endpoints
 }
@@ -127,6 +127,8 @@ class DrawingCanvas {
             }
         }
         
+        this.hideSaliency();
+
         // Hide any error messages
         const errorElement = document.getElementById('error-message');
         if (errorElement) {
@@ -190,6 +192,7 @@ class DrawingCanvas {
             if (response.ok) {
                 const predictions = await response.json();
                 this.updatePredictions(predictions);
+                this.showSaliency(grayscaleArray);
             } else {
                 console.error('Server error:', response.status);
                 this.showError('Server error occurred');
@@ -200,6 +203,42 @@ class DrawingCanvas {
         }
     }
     
+    async showSaliency(grayscaleArray) {
+        const overlay = document.getElementById('saliencyOverlay');
+        if (!overlay) return;
+
+        try {
+            // Heatmap of the pixels that drove the predicted digit
+            const response = await fetch('/explain', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({ image_data: grayscaleArray })
+            });
+
+            if (response.ok) {
+                const heatmap = await response.blob();
+                if (overlay.src) {
+                    URL.revokeObjectURL(overlay.src);
+                }
+                overlay.src = URL.createObjectURL(heatmap);
+                overlay.style.display = 'block';
+            } else {
+                console.error('Server error:', response.status);
+            }
+        } catch (error) {
+            console.error('Network error:', error);
+        }
+    }
+
+    hideSaliency() {
+        const overlay = document.getElementById('saliencyOverlay');
+        if (overlay) {
+            overlay.style.display = 'none';
+        }
+    }
+
     updatePredictions(predictions) {
         // predictions should be an array of 10 probabilities
         for (let i = 0; i < 10; i++) {
@@ -0,0 +1,214 @@
+use std::time::Duration;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use crate::checksum::crc32;
+use crate::error::ModelError;
+use crate::neural_network::NeuralNetwork;
+use crate::training::{BestNetwork, EpochSummary, TrainingState};
+
+// magic, version, then the training state little-endian, the networks as length-prefixed binary networks,
+// and the crc32 of everything before it, the rng is stored as its seed and how many words it has generated
+pub const CHECKPOINT_MAGIC: &[u8; 4] = b"NNCK";
+pub const CHECKPOINT_VERSION: u16 = 1;
+
+pub fn to_checkpoint(network: &NeuralNetwork, state: &TrainingState) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+    result.extend_from_slice(CHECKPOINT_MAGIC);
+    result.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+    result.extend_from_slice(&state.seed.to_le_bytes());
+    result.extend_from_slice(&state.rng.get_word_pos().to_le_bytes());
+    result.extend_from_slice(&state.iteration.to_le_bytes());
+    result.extend_from_slice(&state.samples.to_le_bytes());
+    push_duration(&mut result, state.elapsed);
+    result.extend_from_slice(&state.loss.to_le_bytes());
+    result.extend_from_slice(&state.loss_sum.to_le_bytes());
+    result.extend_from_slice(&state.loss_steps.to_le_bytes());
+    result.extend_from_slice(&(state.epochs.len() as u64).to_le_bytes());
+    for epoch in &state.epochs {
+        result.extend_from_slice(&epoch.epoch.to_le_bytes());
+        result.extend_from_slice(&epoch.samples.to_le_bytes());
+        result.extend_from_slice(&epoch.loss.to_le_bytes());
+        result.extend_from_slice(&epoch.accuracy.to_le_bytes());
+        push_duration(&mut result, epoch.duration);
+    }
+    result.extend_from_slice(&state.epoch_samples.to_le_bytes());
+    result.extend_from_slice(&state.epoch_loss.to_le_bytes());
+    result.extend_from_slice(&state.epoch_correct.to_le_bytes());
+    push_duration(&mut result, state.epoch_elapsed);
+    result.extend_from_slice(&(state.order.len() as u64).to_le_bytes());
+    for &index in &state.order {
+        result.extend_from_slice(&(index as u64).to_le_bytes());
+    }
+    result.extend_from_slice(&(state.position as u64).to_le_bytes());
+    push_network(&mut result, Some(network));
+    match &state.best {
+        Some(best) => {
+            result.push(1);
+            result.extend_from_slice(&best.iteration.to_le_bytes());
+            result.extend_from_slice(&best.accuracy.to_le_bytes());
+            result.extend_from_slice(&best.stale.to_le_bytes());
+            push_network(&mut result, best.network.as_ref());
+        }
+        None => result.push(0),
+    }
+    let checksum = crc32(&result);
+    result.extend_from_slice(&checksum.to_le_bytes());
+    result
+}
+
+pub fn from_checkpoint(input: &[u8]) -> Result<(NeuralNetwork, TrainingState), ModelError> {
+    if !input.starts_with(CHECKPOINT_MAGIC) || input.len() < 10 {
+        return Err(ModelError::InvalidHeader("not a checkpoint".to_string()));
+    }
+    let (body, checksum) = input.split_at(input.len() - 4);
+    let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+    let actual = crc32(body);
+    if expected != actual {
+        return Err(ModelError::ChecksumMismatch { expected, actual });
+    }
+
+    let mut reader = Reader { input: body, position: 4 };
+    let version = u16::from_le_bytes(reader.take()?);
+    if version != CHECKPOINT_VERSION {
+        return Err(ModelError::UnsupportedVersion(version));
+    }
+    let seed = reader.u64()?;
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+    rng.set_word_pos(u128::from_le_bytes(reader.take()?));
+    let mut state = TrainingState {
+        seed,
+        rng,
+        iteration: reader.u64()?,
+        samples: reader.u64()?,
+        elapsed: reader.duration()?,
+        loss: reader.f32()?,
+        loss_sum: reader.f32()?,
+        loss_steps: reader.u64()?,
+        ..TrainingState::new(seed, 0)
+    };
+    for _ in 0..reader.u64()? {
+        state.epochs.push(EpochSummary {
+            epoch: reader.u64()?,
+            samples: reader.u64()?,
+            loss: reader.f32()?,
+            accuracy: reader.f32()?,
+            duration: reader.duration()?,
+        });
+    }
+    state.epoch_samples = reader.u64()?;
+    state.epoch_loss = reader.f32()?;
+    state.epoch_correct = reader.u64()?;
+    state.epoch_elapsed = reader.duration()?;
+    for _ in 0..reader.u64()? {
+        state.order.push(reader.u64()? as usize);
+    }
+    state.position = reader.u64()? as usize;
+    let network = reader.network()?.ok_or(ModelError::InvalidHeader("checkpoint without a network".to_string()))?;
+    if reader.take::<1>()?[0] == 1 {
+        state.best = Some(BestNetwork {
+            iteration: reader.u64()?,
+            accuracy: reader.f32()?,
+            stale: reader.u64()?,
+            network: reader.network()?,
+        });
+    }
+    if reader.position != body.len() {
+        return Err(ModelError::TrailingBytes { count: body.len() - reader.position });
+    }
+    Ok((network, state))
+}
+
+fn push_duration(result: &mut Vec<u8>, duration: Duration) {
+    result.extend_from_slice(&duration.as_secs().to_le_bytes());
+    result.extend_from_slice(&duration.subsec_nanos().to_le_bytes());
+}
+
+// length 0 means no network
+fn push_network(result: &mut Vec<u8>, network: Option<&NeuralNetwork>) {
+    let serialized = network.map_or(Vec::new(), |network| network.serialize_binary());
+    result.extend_from_slice(&(serialized.len() as u64).to_le_bytes());
+    result.extend_from_slice(&serialized);
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ModelError> {
+        let bytes = self.bytes(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&[u8], ModelError> {
+        let bytes = self.input.get(self.position..self.position.saturating_add(count))
+            .ok_or(ModelError::InvalidHeader("truncated checkpoint".to_string()))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, ModelError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, ModelError> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    fn duration(&mut self) -> Result<Duration, ModelError> {
+        let seconds = self.u64()?;
+        Ok(Duration::new(seconds, u32::from_le_bytes(self.take()?)))
+    }
+
+    fn network(&mut self) -> Result<Option<NeuralNetwork>, ModelError> {
+        let length = self.u64()? as usize;
+        if length == 0 {
+            return Ok(None);
+        }
+        NeuralNetwork::try_deserialize_binary_strict(self.bytes(length)?).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use rand::RngCore;
+    use crate::checkpoint::{from_checkpoint, to_checkpoint};
+    use crate::error::ModelError;
+    use crate::neural_network::NeuralNetwork;
+    use crate::training::{BestNetwork, EpochSummary, TrainingState};
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let network = NeuralNetwork::new(&[6, 4, 3]);
+        let mut state = TrainingState::new(42, 5);
+        state.rng.next_u32();
+        state.iteration = 17;
+        state.samples = 51;
+        state.elapsed = Duration::new(3, 250);
+        state.loss_sum = 0.75;
+        state.loss_steps = 2;
+        state.epochs.push(EpochSummary { epoch: 1, samples: 5, loss: 0.3, accuracy: 0.4, duration: Duration::from_millis(12) });
+        state.epoch_correct = 1;
+        state.order = vec![3, 0, 4, 1, 2];
+        state.position = 2;
+        state.best = Some(BestNetwork { iteration: 10, accuracy: 0.6, network: Some(NeuralNetwork::new(&[6, 4, 3])), stale: 1 });
+
+        let checkpoint = to_checkpoint(&network, &state);
+        let (loaded_network, mut loaded_state) = from_checkpoint(&checkpoint).unwrap();
+
+        assert_eq!(loaded_network, network);
+        assert_eq!(loaded_state, state);
+        assert_eq!(loaded_state.rng.next_u64(), state.rng.next_u64());
+    }
+
+    #[test]
+    fn test_checkpoint_checksum() {
+        let mut checkpoint = to_checkpoint(&NeuralNetwork::new(&[2, 2]), &TrainingState::new(1, 3));
+        checkpoint[20] ^= 1;
+
+        assert!(matches!(from_checkpoint(&checkpoint), Err(ModelError::ChecksumMismatch { .. })));
+        assert!(matches!(from_checkpoint(b"NNET0000000000"), Err(ModelError::InvalidHeader(_))));
+    }
+}
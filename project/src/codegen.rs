@@ -0,0 +1,124 @@
+use std::fmt::Write;
+use std::path::Path;
+use crate::neural_network::NeuralNetwork;
+
+// turns a network into Rust source with const weights and a forward function for exactly its layer sizes,
+// so the network can be built into a binary without a model file, for example in build.rs:
+//     let network = neural_network_lib::network_interface::load("networks/digits");
+//     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("digits.rs");
+//     neural_network_lib::codegen::write_rust(&network, out).unwrap();
+// and in the crate:
+//     mod digits { include!(concat!(env!("OUT_DIR"), "/digits.rs")); }
+//     let output: [f32; 10] = digits::process(&input);
+// the generated process gives the same outputs as NeuralNetwork::process, it does the same operations in the same order
+pub fn generate_rust(network: &NeuralNetwork) -> String {
+    let layers = network.layers();
+    let inputs = layers[0];
+    let outputs = layers[layers.len() - 1];
+    let mut result = String::new();
+
+    writeln!(result, "// generated from a network with layers {layers:?}, don't edit").unwrap();
+    writeln!(result).unwrap();
+    writeln!(result, "pub const LAYERS: [usize; {}] = {layers:?};", layers.len()).unwrap();
+    for layer in 0..network.weights.len() {
+        let (layer_inputs, neurons) = (layers[layer], layers[layer + 1]);
+        writeln!(result).unwrap();
+        writeln!(result, "const WEIGHTS_{layer}: [[f32; {layer_inputs}]; {neurons}] = [").unwrap();
+        for row in &network.weights[layer] {
+            writeln!(result, "    [{}],", literals(row)).unwrap();
+        }
+        writeln!(result, "];").unwrap();
+        writeln!(result, "const BIASES_{layer}: [f32; {neurons}] = [{}];", literals(&network.biases[layer])).unwrap();
+    }
+
+    writeln!(result).unwrap();
+    writeln!(result, "pub fn process(input: &[f32; {inputs}]) -> [f32; {outputs}] {{").unwrap();
+    let mut previous = "input".to_string();
+    for layer in 0..network.weights.len() {
+        writeln!(result, "    let layer_{layer} = layer({previous}, &WEIGHTS_{layer}, &BIASES_{layer});").unwrap();
+        previous = format!("&layer_{layer}");
+    }
+    writeln!(result, "    layer_{}", network.weights.len() - 1).unwrap();
+    writeln!(result, "}}").unwrap();
+
+    result.push_str(r#"
+#[allow(clippy::needless_range_loop)]
+fn layer<const INPUTS: usize, const NEURONS: usize>(input: &[f32; INPUTS], weights: &[[f32; INPUTS]; NEURONS], biases: &[f32; NEURONS]) -> [f32; NEURONS] {
+    let mut result = [0.0; NEURONS];
+    for i in 0..NEURONS {
+        let mut tmp = 0.0;
+        for j in 0..INPUTS {
+            tmp += weights[i][j] * input[j];
+        }
+        result[i] = activation(tmp + biases[i]);
+    }
+    result
+}
+
+fn activation(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+"#);
+    result
+}
+
+pub fn write_rust(network: &NeuralNetwork, path: impl AsRef<Path>) -> std::io::Result<()> {
+    std::fs::write(path, generate_rust(network))
+}
+
+// Debug prints the shortest literal that parses back to the same f32
+fn literals(values: &[f32]) -> String {
+    values.iter()
+        .map(|x| match x {
+            x if x.is_nan() => "f32::NAN".to_string(),
+            x if x.is_infinite() && *x > 0.0 => "f32::INFINITY".to_string(),
+            x if x.is_infinite() => "f32::NEG_INFINITY".to_string(),
+            x => format!("{x:?}"),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use crate::codegen::generate_rust;
+    use crate::neural_network::NeuralNetwork;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn test_generated_code_matches_process() {
+        let mut network = NeuralNetwork::new(&[5, 7, 10, 4]);
+        network.weights[0][0][0] = 1e-7;
+        network.biases[1][2] = -0.0;
+        let inputs = [[0.1, 0.8, 0.0, 1.0, 0.5], [1.0, 1.0, 1.0, 1.0, 1.0], [0.0, 0.3, 0.7, 0.2, 0.9]];
+
+        let directory = PathBuf::from(temp_path("codegen"));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("network.rs"), generate_rust(&network)).unwrap();
+        fs::write(directory.join("main.rs"), format!(r#"
+            mod network {{ include!("network.rs"); }}
+            fn main() {{
+                assert_eq!(network::LAYERS, [5, 7, 10, 4]);
+                for input in {inputs:?} {{
+                    let output = network::process(&input);
+                    println!("{{:?}}", output.map(f32::to_bits));
+                }}
+            }}
+        "#)).unwrap();
+        let binary = directory.join("generated");
+        let compiled = Command::new(std::env::var("RUSTC").unwrap_or("rustc".to_string()))
+            .arg("--edition=2024").arg("-o").arg(&binary).arg(directory.join("main.rs"))
+            .status().unwrap();
+        let output = Command::new(&binary).output().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let expected: Vec<String> = inputs.iter()
+            .map(|input| format!("{:?}", network.process(input).iter().map(|x| x.to_bits()).collect::<Vec<u32>>()))
+            .collect();
+        assert!(compiled.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap().lines().collect::<Vec<&str>>(), expected);
+    }
+}
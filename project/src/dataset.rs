@@ -0,0 +1,104 @@
+use std::fs;
+use crate::error::ModelError;
+use crate::image::{get_training_data_path, result_array, HEIGHT, WIDTH};
+
+// samples for training and evaluation, every sample is an input and the one-hot target of its label
+pub trait Dataset {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // input and target of the sample
+    fn get(&self, index: usize) -> (&[f32], &[f32]);
+
+    // the digit of every sample
+    fn labels(&self) -> &[u8];
+}
+
+// the images are decoded once, all inputs are in one buffer and all targets in another,
+// so training and evaluation don't touch the disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct InMemoryDataset {
+    inputs: Vec<f32>,
+    targets: Vec<f32>,
+    labels: Vec<u8>,
+}
+
+const INPUT_SIZE: usize = WIDTH * HEIGHT;
+const TARGET_SIZE: usize = 10;
+
+impl InMemoryDataset {
+    // reads {catalog}/{digit}/{digit}/*.png, the files of every digit sorted by name, so the order is always the same
+    pub fn load(catalog: &str) -> Self {
+        Self::try_load(catalog).unwrap()
+    }
+
+    // the same as load, but a missing or unreadable directory is an error
+    pub fn try_load(catalog: &str) -> Result<Self, ModelError> {
+        let mut result = InMemoryDataset { inputs: Vec::new(), targets: Vec::new(), labels: Vec::new() };
+        for digit in 0..10 {
+            let mut files: Vec<String> = fs::read_dir(format!("{catalog}/{digit}/{digit}/"))?
+                .map(|x| x.map(|x| x.path().display().to_string()))
+                .collect::<Result<Vec<String>, _>>()?;
+            files.retain(|x| x.ends_with(".png"));
+            files.sort();
+            for file in files {
+                let (input, target) = get_training_data_path(&file, digit);
+                result.push(&input, &target, digit);
+            }
+        }
+        Ok(result)
+    }
+
+    // inputs of WIDTH * HEIGHT values and their digits
+    pub fn from_samples(inputs: &[Vec<f32>], labels: &[u8]) -> Self {
+        assert_eq!(inputs.len(), labels.len());
+        let mut result = InMemoryDataset { inputs: Vec::new(), targets: Vec::new(), labels: Vec::new() };
+        for (input, &label) in inputs.iter().zip(labels) {
+            assert_eq!(input.len(), INPUT_SIZE);
+            result.push(input, &result_array(label), label);
+        }
+        result
+    }
+
+    fn push(&mut self, input: &[f32], target: &[f32], label: u8) {
+        self.inputs.extend_from_slice(input);
+        self.targets.extend_from_slice(target);
+        self.labels.push(label);
+    }
+}
+
+impl Dataset for InMemoryDataset {
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    fn get(&self, index: usize) -> (&[f32], &[f32]) {
+        (&self.inputs[index * INPUT_SIZE..(index + 1) * INPUT_SIZE], &self.targets[index * TARGET_SIZE..(index + 1) * TARGET_SIZE])
+    }
+
+    fn labels(&self) -> &[u8] {
+        &self.labels
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataset::{Dataset, InMemoryDataset};
+    use crate::image::{HEIGHT, WIDTH};
+
+    #[test]
+    fn test_from_samples() {
+        let inputs = vec![vec![0.5; WIDTH * HEIGHT], vec![1.0; WIDTH * HEIGHT]];
+        let dataset = InMemoryDataset::from_samples(&inputs, &[3, 7]);
+
+        let (input, target) = dataset.get(1);
+
+        assert_eq!(dataset.len(), 2);
+        assert_eq!(dataset.labels(), &[3, 7]);
+        assert_eq!(input, inputs[1].as_slice());
+        assert_eq!(target, &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use crate::network_math;
+use crate::neural_network::NeuralNetwork;
+
+// how many of the most changed neurons are reported per layer
+pub const MOST_CHANGED_NEURONS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchitectureMismatch {
+    pub left: Vec<u32>,
+    pub right: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerDiff {
+    // 1 is the first layer after the input
+    pub layer: usize,
+    // weights and biases
+    pub parameters: usize,
+    pub changed: usize,
+    pub max_change: f32,
+    pub mean_change: f32,
+    // (neuron, sum of absolute changes of its weights and bias), the most changed first
+    pub most_changed_neurons: Vec<(usize, f32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDivergence {
+    pub samples: usize,
+    pub mean_difference: f32,
+    pub max_difference: f32,
+    // fraction of the samples for which both networks predict the same class
+    pub same_prediction: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkDiff {
+    pub layers: Vec<LayerDiff>,
+    pub outputs: OutputDivergence,
+}
+
+impl LayerDiff {
+    pub fn changed_fraction(&self) -> f32 {
+        self.changed as f32 / self.parameters as f32
+    }
+}
+
+pub fn diff(left: &NeuralNetwork, right: &NeuralNetwork, samples: &[Vec<f32>]) -> Result<NetworkDiff, ArchitectureMismatch> {
+    if left.layers() != right.layers() {
+        return Err(ArchitectureMismatch { left: left.layers(), right: right.layers() });
+    }
+
+    let mut layers = Vec::new();
+    for layer in 0..left.weights.len() {
+        let mut changed = 0;
+        let mut parameters = 0;
+        let mut max_change: f32 = 0.0;
+        let mut total_change = 0.0;
+        let mut neurons: Vec<(usize, f32)> = Vec::new();
+        for neuron in 0..left.biases[layer].len() {
+            let old = left.weights[layer][neuron].iter().chain([&left.biases[layer][neuron]]);
+            let new = right.weights[layer][neuron].iter().chain([&right.biases[layer][neuron]]);
+            let mut neuron_change = 0.0;
+            for (a, b) in old.zip(new) {
+                let change = (a - b).abs();
+                changed += (a != b) as usize;
+                parameters += 1;
+                max_change = max_change.max(change);
+                neuron_change += change;
+            }
+            total_change += neuron_change;
+            neurons.push((neuron, neuron_change));
+        }
+        neurons.sort_by(|a, b| b.1.total_cmp(&a.1));
+        neurons.truncate(MOST_CHANGED_NEURONS);
+        layers.push(LayerDiff {
+            layer: layer + 1,
+            parameters,
+            changed,
+            max_change,
+            mean_change: total_change / parameters as f32,
+            most_changed_neurons: neurons,
+        });
+    }
+
+    let mut total_difference = 0.0;
+    let mut max_difference: f32 = 0.0;
+    let mut same_prediction = 0;
+    for sample in samples {
+        let a = left.process(sample);
+        let b = right.process(sample);
+        for (x, y) in a.iter().zip(b.iter()) {
+            total_difference += (x - y).abs();
+            max_difference = max_difference.max((x - y).abs());
+        }
+        same_prediction += (network_math::argmax(&a) == network_math::argmax(&b)) as usize;
+    }
+    let outputs_count = samples.len() * left.biases.last().map_or(0, |b| b.len());
+    let outputs = OutputDivergence {
+        samples: samples.len(),
+        mean_difference: if outputs_count == 0 { 0.0 } else { total_difference / outputs_count as f32 },
+        max_difference,
+        same_prediction: if samples.is_empty() { 0.0 } else { same_prediction as f32 / samples.len() as f32 },
+    };
+
+    Ok(NetworkDiff { layers, outputs })
+}
+
+impl fmt::Display for ArchitectureMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "architectures don't match: {:?} vs {:?}", self.left, self.right)
+    }
+}
+
+impl fmt::Display for NetworkDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for layer in &self.layers {
+            writeln!(f, "layer {}; changed {}; total {}; {:.2}%; max change {}; mean change {}",
+                     layer.layer, layer.changed, layer.parameters, layer.changed_fraction() * 100.0, layer.max_change, layer.mean_change)?;
+            writeln!(f, "  most changed neurons {:?}", layer.most_changed_neurons)?;
+        }
+        if self.outputs.samples > 0 {
+            writeln!(f, "outputs over {} samples; mean difference {}; max difference {}; same prediction {:.2}%",
+                     self.outputs.samples, self.outputs.mean_difference, self.outputs.max_difference, self.outputs.same_prediction * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diff::diff;
+    use crate::neural_network::NeuralNetwork;
+
+    #[test]
+    fn test_diff() {
+        let old = NeuralNetwork::new(&[2, 3, 2]);
+        let mut new = old.clone();
+        new.weights[1][1][2] += 0.5;
+        new.biases[1][0] -= 0.25;
+
+        let result = diff(&old, &new, &[vec![0.1, 0.9], vec![0.5, 0.5]]).unwrap();
+
+        assert_eq!(result.layers.len(), 2);
+        assert_eq!(result.layers[0].changed, 0);
+        assert_eq!(result.layers[0].parameters, 9);
+        assert_eq!(result.layers[1].changed, 2);
+        assert_eq!(result.layers[1].parameters, 8);
+        assert!((result.layers[1].max_change - 0.5).abs() < 0.0001);
+        assert_eq!(result.layers[1].most_changed_neurons[0].0, 1);
+        assert_eq!(result.outputs.samples, 2);
+        assert!(result.outputs.max_difference > 0.0);
+    }
+
+    #[test]
+    fn test_diff_architecture_mismatch() {
+        let result = diff(&NeuralNetwork::new(&[2, 3, 2]), &NeuralNetwork::new(&[2, 4, 2]), &[]);
+
+        let error = result.unwrap_err();
+        assert_eq!(error.left, vec![2, 3, 2]);
+        assert_eq!(error.right, vec![2, 4, 2]);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::ModelError;
+use crate::network_interface::try_load;
+use crate::network_math;
+use crate::neural_network::NeuralNetwork;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Combination {
+    Mean,
+    // one weight per member, the weights don't have to sum up to 1
+    WeightedMean(Vec<f32>),
+    // every member votes for its highest output, the result is the fraction of votes per class
+    MajorityVote,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ensemble {
+    pub members: Vec<NeuralNetwork>,
+    pub combination: Combination,
+}
+
+impl Ensemble {
+    // the members must have the same input and output sizes, the hidden layers may differ
+    pub fn new(members: Vec<NeuralNetwork>, combination: Combination) -> Result<Self, ModelError> {
+        let first = members.first().ok_or(ModelError::InvalidEnsemble("no networks".to_string()))?.layers();
+        for (i, member) in members.iter().enumerate().skip(1) {
+            let layers = member.layers();
+            if layers[0] != first[0] || layers.last() != first.last() {
+                return Err(ModelError::ShapeMismatch {
+                    name: format!("ensemble member {i}"),
+                    expected: vec![first[0] as usize, *first.last().unwrap() as usize],
+                    actual: vec![layers[0] as usize, *layers.last().unwrap() as usize],
+                });
+            }
+        }
+        if let Combination::WeightedMean(weights) = &combination {
+            if weights.len() != members.len() {
+                return Err(ModelError::ShapeMismatch { name: "ensemble weights".to_string(), expected: vec![members.len()], actual: vec![weights.len()] });
+            }
+            // the combined output is divided by the sum
+            if !weights.iter().all(|x| x.is_finite() && *x >= 0.0) {
+                return Err(ModelError::InvalidEnsemble(format!("weights must be finite and not negative: {weights:?}")));
+            }
+            if weights.iter().sum::<f32>() <= 0.0 {
+                return Err(ModelError::InvalidEnsemble(format!("weights must not sum up to 0: {weights:?}")));
+            }
+        }
+        Ok(Ensemble { members, combination })
+    }
+
+    pub fn load(files: &[&str], combination: Combination) -> Result<Self, ModelError> {
+        let members = files.iter().map(|&file| try_load(file)).collect::<Result<_, _>>()?;
+        Ensemble::new(members, combination)
+    }
+
+    pub fn process(&self, input: &[f32]) -> Vec<f32> {
+        let outputs = self.member_outputs(input);
+        self.combine(&outputs)
+    }
+
+    // mean absolute difference between the output of every member and the combined output,
+    // 0.0 means the member fully agrees with the ensemble
+    pub fn disagreement(&self, input: &[f32]) -> Vec<f32> {
+        let outputs = self.member_outputs(input);
+        let combined = self.combine(&outputs);
+        outputs.iter()
+            .map(|output| {
+                let total: f32 = output.iter().zip(combined.iter()).map(|(a, b)| (a - b).abs()).sum();
+                total / combined.len() as f32
+            })
+            .collect()
+    }
+
+    // mean of the input gradients of all members
+    pub fn input_gradient(&self, input: &[f32], output: usize) -> Vec<f32> {
+        let mut result = vec![0.0; input.len()];
+        for network in &self.members {
+            network_math::sum(&mut result, &network.input_gradient(input, output));
+        }
+        result.iter_mut().for_each(|x| *x /= self.members.len() as f32);
+        result
+    }
+
+    fn member_outputs(&self, input: &[f32]) -> Vec<Vec<f32>> {
+        self.members.iter().map(|network| network.process(input)).collect()
+    }
+
+    fn combine(&self, outputs: &[Vec<f32>]) -> Vec<f32> {
+        let mut result = vec![0.0; outputs[0].len()];
+        match &self.combination {
+            Combination::Mean => {
+                for output in outputs {
+                    network_math::sum(&mut result, output);
+                }
+                result.iter_mut().for_each(|x| *x /= outputs.len() as f32);
+            }
+            Combination::WeightedMean(weights) => {
+                for (output, weight) in outputs.iter().zip(weights.iter()) {
+                    for (x, value) in result.iter_mut().zip(output.iter()) {
+                        *x += value * weight;
+                    }
+                }
+                let total: f32 = weights.iter().sum();
+                result.iter_mut().for_each(|x| *x /= total);
+            }
+            Combination::MajorityVote => {
+                for output in outputs {
+                    result[network_math::argmax(output)] += 1.0;
+                }
+                result.iter_mut().for_each(|x| *x /= outputs.len() as f32);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ensemble::{Combination, Ensemble};
+    use crate::error::ModelError;
+    use crate::neural_network::NeuralNetwork;
+
+    // single layer network, the output is sigmoid of the bias because the input is all zeros
+    fn constant_network(biases: &[f32]) -> NeuralNetwork {
+        let mut network = NeuralNetwork::new(&[1, biases.len() as u32]);
+        network.biases = vec![biases.to_vec()];
+        network
+    }
+
+    fn members() -> Vec<NeuralNetwork> {
+        vec![
+            constant_network(&[2.0, -2.0]),
+            constant_network(&[1.0, -1.0]),
+            constant_network(&[-3.0, 3.0]),
+        ]
+    }
+
+    #[test]
+    fn test_mean() {
+        let ensemble = Ensemble::new(members(), Combination::Mean).unwrap();
+        let outputs: Vec<Vec<f32>> = ensemble.members.iter().map(|x| x.process(&[0.0])).collect();
+
+        let result = ensemble.process(&[0.0]);
+
+        let expected = (outputs[0][0] + outputs[1][0] + outputs[2][0]) / 3.0;
+        assert!((result[0] - expected).abs() < 0.0001);
+        assert!((result[0] + result[1] - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_weighted_mean() {
+        let ensemble = Ensemble::new(members(), Combination::WeightedMean(vec![0.0, 2.0, 0.0])).unwrap();
+
+        let result = ensemble.process(&[0.0]);
+
+        assert_eq!(result, ensemble.members[1].process(&[0.0]));
+    }
+
+    #[test]
+    fn test_weighted_mean_invalid_weights() {
+        let zero = Ensemble::new(members(), Combination::WeightedMean(vec![0.0, 0.0, 0.0]));
+        let negative = Ensemble::new(members(), Combination::WeightedMean(vec![1.0, -1.0, 1.0]));
+        let missing = Ensemble::new(members(), Combination::WeightedMean(vec![1.0, 1.0]));
+
+        assert!(matches!(zero, Err(ModelError::InvalidEnsemble(message)) if message.starts_with("weights must not sum up to 0")));
+        assert!(matches!(negative, Err(ModelError::InvalidEnsemble(message)) if message.starts_with("weights must be finite and not negative")));
+        assert!(matches!(missing, Err(ModelError::ShapeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_mismatched_members() {
+        let mut wider = members();
+        wider.push(constant_network(&[1.0, 1.0, 1.0]));
+        let mut different_input = members();
+        different_input.push(NeuralNetwork::new(&[2, 3, 2]));
+
+        assert!(matches!(Ensemble::new(wider, Combination::Mean),
+            Err(ModelError::ShapeMismatch { name, expected, actual }) if name == "ensemble member 3" && expected == vec![1, 2] && actual == vec![1, 3]));
+        assert!(matches!(Ensemble::new(different_input, Combination::Mean), Err(ModelError::ShapeMismatch { .. })));
+        assert!(matches!(Ensemble::new(vec![], Combination::Mean), Err(ModelError::InvalidEnsemble(_))));
+        // only the hidden layers differ
+        assert!(Ensemble::new(vec![NeuralNetwork::new(&[1, 4, 2]), NeuralNetwork::new(&[1, 2])], Combination::Mean).is_ok());
+    }
+
+    #[test]
+    fn test_majority_vote() {
+        let ensemble = Ensemble::new(members(), Combination::MajorityVote).unwrap();
+
+        let result = ensemble.process(&[0.0]);
+
+        assert_eq!(result, vec![2.0 / 3.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn test_disagreement() {
+        let ensemble = Ensemble::new(members(), Combination::Mean).unwrap();
+
+        let disagreement = ensemble.disagreement(&[0.0]);
+
+        assert_eq!(disagreement.len(), 3);
+        assert!(disagreement[2] > disagreement[0]);
+        assert!(disagreement[2] > disagreement[1]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(io::Error),
+    // text networks must be valid UTF-8
+    InvalidText,
+    // no line with the layer sizes, for example "784 800 10"
+    MissingHeader,
+    // the header contains a size that isn't a positive integer, layer 0 is the input
+    BadLayerSize { layer: usize, value: String },
+    UnsupportedVersion(u16),
+    // the file ended before all weights and biases were read
+    UnexpectedEof { expected: usize, actual: usize },
+    UnparsableValue { line: usize, value: String },
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // only in strict mode, data after the last value
+    TrailingLine { line: usize, value: String },
+    TrailingBytes { count: usize },
+    // only in strict mode, a "layer N" section without (inputs + 1) * neurons values, line is the "layer N" line
+    SectionSize { layer: usize, line: usize, inputs: usize, neurons: usize, actual: usize },
+    // only in strict mode, the line should have been the marker of the next layer
+    UnexpectedSection { line: usize, expected: String, actual: String },
+    // weights or biases don't match the layer sizes
+    ShapeMismatch { name: String, expected: Vec<usize>, actual: Vec<usize> },
+    // safetensors, npy, zip or checkpoint header that can't be read
+    InvalidHeader(String),
+    MissingTensor(String),
+    UnsupportedDtype { name: String, dtype: String },
+    // no members or weights that can't be combined
+    InvalidEnsemble(String),
+    // a training config that can't be trained with, like an empty dataset
+    InvalidConfig(String),
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "{e}"),
+            ModelError::InvalidText => write!(f, "the network is neither binary nor valid text"),
+            ModelError::MissingHeader => write!(f, "missing header with the layer sizes"),
+            ModelError::BadLayerSize { layer, value } => write!(f, "bad size of layer {layer}: \"{value}\""),
+            ModelError::UnsupportedVersion(version) => write!(f, "unsupported binary network version {version}"),
+            ModelError::UnexpectedEof { expected, actual } => write!(f, "unexpected end of file, expected {expected} values, found {actual}"),
+            ModelError::UnparsableValue { line, value } => write!(f, "line {line}: can't parse \"{value}\""),
+            ModelError::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch, expected {expected:08x}, calculated {actual:08x}"),
+            ModelError::TrailingLine { line, value } => write!(f, "line {line}: unexpected \"{value}\" after the last value"),
+            ModelError::TrailingBytes { count } => write!(f, "unexpected {count} bytes after the last value"),
+            ModelError::SectionSize { layer, line, inputs, neurons, actual } => write!(f,
+                "line {line}: layer {layer} has {actual} values, expected {}, {neurons} neurons with {inputs} weights and a bias each",
+                (inputs + 1) * neurons),
+            ModelError::UnexpectedSection { line, expected, actual } => write!(f, "line {line}: expected \"{expected}\", found \"{actual}\""),
+            ModelError::ShapeMismatch { name, expected, actual } => write!(f, "{name}: expected shape {expected:?}, found {actual:?}"),
+            ModelError::InvalidHeader(message) => write!(f, "invalid header: {message}"),
+            ModelError::MissingTensor(name) => write!(f, "missing tensor {name}"),
+            ModelError::UnsupportedDtype { name, dtype } => write!(f, "{name}: unsupported dtype {dtype}, only F32 is supported"),
+            ModelError::InvalidEnsemble(message) => write!(f, "invalid ensemble: {message}"),
+            ModelError::InvalidConfig(message) => write!(f, "invalid training config: {message}"),
+            #[cfg(feature = "serde")]
+            ModelError::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<io::Error> for ModelError {
+    fn from(e: io::Error) -> Self {
+        ModelError::Io(e)
+    }
+}
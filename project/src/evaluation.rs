@@ -0,0 +1,320 @@
+use std::fmt;
+use crate::dataset::Dataset;
+use crate::network_math;
+use crate::neural_network::NeuralNetwork;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    pub samples: usize,
+    pub accuracy: f32,
+    // mean of the loss training minimizes
+    pub loss: f32,
+    // per digit, the number of samples with that label and the fraction of them that was recognized
+    pub class_samples: Vec<usize>,
+    pub class_accuracy: Vec<f32>,
+}
+
+// runs the network over every sample of the dataset
+pub fn evaluate(network: &NeuralNetwork, dataset: &dyn Dataset) -> EvalReport {
+    let classes = network.biases.last().unwrap().len();
+    let mut class_samples = vec![0; classes];
+    let mut class_correct = vec![0; classes];
+    let mut loss = 0.0;
+    for i in 0..dataset.len() {
+        let (input, target) = dataset.get(i);
+        let label = dataset.labels()[i] as usize;
+        let output = network.process(input);
+        loss += network_math::squared_error(&output, target);
+        class_samples[label] += 1;
+        if network_math::argmax(&output) == label {
+            class_correct[label] += 1;
+        }
+    }
+
+    let samples = dataset.len();
+    EvalReport {
+        samples,
+        accuracy: class_correct.iter().sum::<usize>() as f32 / samples.max(1) as f32,
+        loss: loss / samples.max(1) as f32,
+        class_accuracy: class_correct.iter().zip(&class_samples).map(|(&correct, &samples)| correct as f32 / samples.max(1) as f32).collect(),
+        class_samples,
+    }
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "samples: {}, accuracy: {:.2}%, loss: {:.6}", self.samples, self.accuracy * 100.0, self.loss)?;
+        write!(f, "digit  samples  accuracy")?;
+        for (digit, (samples, accuracy)) in self.class_samples.iter().zip(&self.class_accuracy).enumerate() {
+            write!(f, "\n{digit:>5}  {samples:>7}  {:>7.2}%", accuracy * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+// the table and the csv show top-k accuracy for these k, top-1 is the recall
+pub const TOP_K: [usize; 2] = [2, 3];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfusionMatrix {
+    // counts[label][predicted], the predicted digit is the highest output
+    pub counts: Vec<Vec<usize>>,
+    // ranks[label][r], the number of samples whose label had the r-th highest output
+    pub ranks: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassMetrics {
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    // samples with that label
+    pub support: usize,
+}
+
+// runs the network over every sample of the dataset
+pub fn confusion_matrix(network: &NeuralNetwork, dataset: &dyn Dataset) -> ConfusionMatrix {
+    let mut result = ConfusionMatrix::new(network.biases.last().unwrap().len());
+    for i in 0..dataset.len() {
+        let (input, _target) = dataset.get(i);
+        result.add(&network.process(input), dataset.labels()[i] as usize);
+    }
+    result
+}
+
+impl ConfusionMatrix {
+    pub fn new(classes: usize) -> Self {
+        ConfusionMatrix { counts: vec![vec![0; classes]; classes], ranks: vec![vec![0; classes]; classes] }
+    }
+
+    pub fn add(&mut self, output: &[f32], label: usize) {
+        self.counts[label][network_math::argmax(output)] += 1;
+        // equal outputs before the label rank above it, like argmax picks the first of equal outputs
+        let rank = output.iter().enumerate().filter(|&(i, &x)| x > output[label] || (x == output[label] && i < label)).count();
+        self.ranks[label][rank] += 1;
+    }
+
+    pub fn classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn samples(&self) -> usize {
+        self.counts.iter().flatten().sum()
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        self.top_k_accuracy(1)
+    }
+
+    // fraction of the samples whose label is one of the k highest outputs
+    pub fn top_k_accuracy(&self, k: usize) -> f32 {
+        let correct: usize = self.ranks.iter().map(|ranks| ranks.iter().take(k).sum::<usize>()).sum();
+        correct as f32 / self.samples().max(1) as f32
+    }
+
+    // the same for the samples of one digit
+    pub fn class_top_k_accuracy(&self, class: usize, k: usize) -> f32 {
+        self.ranks[class].iter().take(k).sum::<usize>() as f32 / self.ranks[class].iter().sum::<usize>().max(1) as f32
+    }
+
+    // a class that was never predicted has precision 0, one without samples has recall 0
+    pub fn class_metrics(&self, class: usize) -> ClassMetrics {
+        let correct = self.counts[class][class];
+        let predicted: usize = self.counts.iter().map(|row| row[class]).sum();
+        let support: usize = self.counts[class].iter().sum();
+        let precision = correct as f32 / predicted.max(1) as f32;
+        let recall = correct as f32 / support.max(1) as f32;
+        let f1 = if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 };
+        ClassMetrics { precision, recall, f1, support }
+    }
+
+    // unweighted mean over the digits, support is the total
+    pub fn macro_average(&self) -> ClassMetrics {
+        self.average(|_| 1.0 / self.classes() as f32)
+    }
+
+    // mean over the digits weighted by their support, the recall is the accuracy
+    pub fn weighted_average(&self) -> ClassMetrics {
+        let samples = self.samples().max(1) as f32;
+        self.average(|metrics| metrics.support as f32 / samples)
+    }
+
+    fn average(&self, weight: impl Fn(&ClassMetrics) -> f32) -> ClassMetrics {
+        let mut result = ClassMetrics { precision: 0.0, recall: 0.0, f1: 0.0, support: 0 };
+        for class in 0..self.classes() {
+            let metrics = self.class_metrics(class);
+            let weight = weight(&metrics);
+            result.precision += weight * metrics.precision;
+            result.recall += weight * metrics.recall;
+            result.f1 += weight * metrics.f1;
+            result.support += metrics.support;
+        }
+        result
+    }
+
+    // one row per label with the predictions, the metrics and top-k accuracy, then the macro and weighted averages
+    pub fn to_csv(&self) -> String {
+        let mut result = String::from("label");
+        for predicted in 0..self.classes() {
+            result.push_str(&format!(",{predicted}"));
+        }
+        result.push_str(",precision,recall,f1");
+        for k in TOP_K {
+            result.push_str(&format!(",top_{k}"));
+        }
+        result.push_str(",support\n");
+        for class in 0..self.classes() {
+            result.push_str(&class.to_string());
+            for count in &self.counts[class] {
+                result.push_str(&format!(",{count}"));
+            }
+            let top_k: Vec<f32> = TOP_K.iter().map(|&k| self.class_top_k_accuracy(class, k)).collect();
+            result.push_str(&csv_metrics(&self.class_metrics(class), &top_k));
+        }
+        let top_k: Vec<f32> = TOP_K.iter().map(|&k| self.top_k_accuracy(k)).collect();
+        let macro_top_k: Vec<f32> = TOP_K.iter()
+            .map(|&k| (0..self.classes()).map(|class| self.class_top_k_accuracy(class, k)).sum::<f32>() / self.classes() as f32)
+            .collect();
+        for (name, metrics, top_k) in [("macro", self.macro_average(), macro_top_k), ("weighted", self.weighted_average(), top_k)] {
+            result.push_str(name);
+            result.push_str(&",".repeat(self.classes()));
+            result.push_str(&csv_metrics(&metrics, &top_k));
+        }
+        result
+    }
+}
+
+fn csv_metrics(metrics: &ClassMetrics, top_k: &[f32]) -> String {
+    let mut result = format!(",{},{},{}", metrics.precision, metrics.recall, metrics.f1);
+    for accuracy in top_k {
+        result.push_str(&format!(",{accuracy}"));
+    }
+    result.push_str(&format!(",{}\n", metrics.support));
+    result
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "label \\ predicted")?;
+        write!(f, "     ")?;
+        for predicted in 0..self.classes() {
+            write!(f, " {predicted:>5}")?;
+        }
+        writeln!(f)?;
+        for (label, row) in self.counts.iter().enumerate() {
+            write!(f, "{label:>5}")?;
+            for count in row {
+                write!(f, " {count:>5}")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "digit    precision  recall      f1  support")?;
+        for class in 0..self.classes() {
+            writeln!(f, "{}", metrics_row(&class.to_string(), &self.class_metrics(class)))?;
+        }
+        writeln!(f, "{}", metrics_row("macro", &self.macro_average()))?;
+        writeln!(f, "{}", metrics_row("weighted", &self.weighted_average()))?;
+        writeln!(f)?;
+        write!(f, "accuracy: {:.2}%", self.accuracy() * 100.0)?;
+        for k in TOP_K {
+            write!(f, ", top-{k}: {:.2}%", self.top_k_accuracy(k) * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+fn metrics_row(name: &str, metrics: &ClassMetrics) -> String {
+    format!("{name:<8} {:>9.4} {:>7.4} {:>7.4} {:>8}", metrics.precision, metrics.recall, metrics.f1, metrics.support)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataset::InMemoryDataset;
+    use crate::evaluation::{confusion_matrix, evaluate, ConfusionMatrix};
+    use crate::image::{HEIGHT, WIDTH};
+    use crate::neural_network::NeuralNetwork;
+
+    // the output of digit d follows input pixel d, so images with only pixel d set are recognized as d
+    fn pixel_network() -> NeuralNetwork {
+        let weights = (0..10)
+            .map(|digit| (0..WIDTH * HEIGHT).map(|pixel| if pixel == digit { 20.0 } else { 0.0 }).collect())
+            .collect();
+        NeuralNetwork::from_parameters(vec![weights], vec![vec![-10.0; 10]])
+    }
+
+    fn image(pixel: usize) -> Vec<f32> {
+        let mut result = vec![0.0; WIDTH * HEIGHT];
+        result[pixel] = 1.0;
+        result
+    }
+
+    #[test]
+    fn test_evaluate() {
+        // two threes, one recognized as a five, and one correct seven
+        let dataset = InMemoryDataset::from_samples(&[image(3), image(5), image(7)], &[3, 3, 7]);
+
+        let report = evaluate(&pixel_network(), &dataset);
+
+        assert_eq!(report.samples, 3);
+        assert!((report.accuracy - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(report.class_samples, vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(report.class_accuracy, vec![0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        // a correct output costs about 0, a wrong one about 1 (0.5 for the missed digit and 0.5 for the wrong one)
+        assert!((report.loss - 1.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_confusion_matrix() {
+        let mut matrix = ConfusionMatrix::new(3);
+        // label 0: one correct, one predicted as 1 with 0 second
+        matrix.add(&[0.9, 0.1, 0.0], 0);
+        matrix.add(&[0.4, 0.6, 0.0], 0);
+        // label 1: correct
+        matrix.add(&[0.1, 0.8, 0.1], 1);
+        // label 2: predicted as 1 with 2 last
+        matrix.add(&[0.3, 0.5, 0.2], 2);
+
+        assert_eq!(matrix.counts, vec![vec![1, 1, 0], vec![0, 1, 0], vec![0, 1, 0]]);
+        assert_eq!(matrix.samples(), 4);
+        assert_eq!(matrix.accuracy(), 0.5);
+        assert_eq!(matrix.top_k_accuracy(2), 0.75);
+        assert_eq!(matrix.top_k_accuracy(3), 1.0);
+
+        let metrics = matrix.class_metrics(1);
+        assert!((metrics.precision - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(metrics.recall, 1.0);
+        assert!((metrics.f1 - 0.5).abs() < 1e-6);
+        assert_eq!(metrics.support, 1);
+        // never predicted, never right
+        assert_eq!(matrix.class_metrics(2).f1, 0.0);
+
+        let macro_average = matrix.macro_average();
+        assert!((macro_average.recall - 0.5).abs() < 1e-6);
+        assert_eq!(macro_average.support, 4);
+        let weighted = matrix.weighted_average();
+        assert!((weighted.recall - matrix.accuracy()).abs() < 1e-6);
+        assert!((weighted.precision - (0.5 + 1.0 / 3.0 / 4.0)).abs() < 1e-6);
+
+        let csv = matrix.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "label,0,1,2,precision,recall,f1,top_2,top_3,support");
+        assert_eq!(lines[1], "0,1,1,0,1,0.5,0.6666667,1,1,2");
+        assert_eq!(lines[3], "2,0,1,0,0,0,0,0,1,1");
+        assert!(lines[5].starts_with("weighted,,,,"));
+        assert_eq!(lines.len(), 6);
+    }
+
+    #[test]
+    fn test_confusion_matrix_of_network() {
+        let dataset = InMemoryDataset::from_samples(&[image(3), image(5), image(7)], &[3, 3, 7]);
+
+        let matrix = confusion_matrix(&pixel_network(), &dataset);
+
+        assert_eq!(matrix.classes(), 10);
+        assert_eq!(matrix.counts[3][5], 1);
+        assert_eq!(matrix.counts[3][3], 1);
+        assert_eq!(matrix.counts[7][7], 1);
+        assert_eq!(matrix.accuracy(), evaluate(&pixel_network(), &dataset).accuracy);
+    }
+}
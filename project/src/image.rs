@@ -1,9 +1,10 @@
-use image::{GenericImageView, ImageBuffer, ImageReader, Rgba, RgbaImage};
+use std::io::Cursor;
+use image::{GenericImageView, ImageBuffer, ImageFormat, ImageReader, Rgba, RgbaImage};
 
 pub const WIDTH: usize = 28;
 pub const HEIGHT: usize = 28;
 
-fn result_array(digit: u8) -> [f32; 10] {
+pub(crate) fn result_array(digit: u8) -> [f32; 10] {
     match digit {
         0 => [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
         1 => [0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
@@ -54,6 +55,32 @@ pub fn save_training_data(catalog: &str, digit: u8, image_data: &[f32], index: u
     img.save(path).unwrap();
 }
 
+// positive values are red, negative values are blue, the stronger the value the less transparent the pixel,
+// the values are scaled so the strongest one is fully opaque
+pub fn heatmap(values: &[f32]) -> RgbaImage {
+    let max = values.iter().fold(0.0_f32, |acc, x| acc.max(x.abs()));
+    let mut img = RgbaImage::new(WIDTH as u32, HEIGHT as u32);
+    for col in 0..HEIGHT {
+        for row in 0..WIDTH {
+            let value = values[col * HEIGHT + row];
+            let alpha = if max > 0.0 { (value.abs() / max * 255.0) as u8 } else { 0 };
+            let pixel = if value >= 0.0 { Rgba::from([255, 0, 0, alpha]) } else { Rgba::from([0, 0, 255, alpha]) };
+            img.put_pixel(row as u32, col as u32, pixel);
+        }
+    }
+    img
+}
+
+pub fn heatmap_png(values: &[f32]) -> Vec<u8> {
+    let mut result = Vec::new();
+    heatmap(values).write_to(&mut Cursor::new(&mut result), ImageFormat::Png).unwrap();
+    result
+}
+
+pub fn save_heatmap(path: &str, values: &[f32]) {
+    heatmap(values).save(path).unwrap();
+}
+
 pub fn read() {
     let path = "C:\\Users\\jakubbraz\\Downloads\\archive\\dataset\\3\\3\\999.png";
     let zero_pixel = Rgba::from([0, 0, 0, 0]);
@@ -2,3 +2,21 @@ pub mod network_interface;
 pub mod neural_network;
 mod network_math;
 pub mod image;
+pub mod ensemble;
+pub mod statistics;
+pub mod diff;
+pub mod saliency;
+pub mod error;
+mod checksum;
+pub mod metadata;
+pub mod safetensors;
+pub mod numpy;
+pub mod mapped;
+pub mod codegen;
+pub mod training;
+pub mod dataset;
+pub mod evaluation;
+pub mod checkpoint;
+pub mod metrics;
+#[cfg(test)]
+mod test_util;
@@ -1,35 +1,32 @@
-use std::fs::read_to_string;
-use std::num::ParseFloatError;
-use crate::image::{get_training_data, read, HEIGHT, WIDTH};
-use crate::network_interface::{create, learn, load, save, test_data};
+use crate::diff::diff;
+use crate::image::{HEIGHT, WIDTH};
+use crate::dataset::InMemoryDataset;
+use crate::metrics::{MetricsFormat, MetricsLog};
+use crate::network_interface::{confusion_matrix_dataset, learn_with_callbacks, load, resume_with_callbacks, sample_inputs, save, save_with_metadata, test_data};
 use crate::neural_network::NeuralNetwork;
+use crate::statistics::statistics;
+use crate::training::{EarlyStopping, ProgressLogger, TrainingConfig};
 
 mod network_interface;
 mod neural_network;
 mod network_math;
 mod image;
-
-// fn main() {
-//     // let s1 = read_to_string("networks/3_000_000_iterations/after_learn_network").unwrap();
-//     let s1 = read_to_string("networks/after_learn_network").unwrap();
-//     // let s2 = read_to_string("networks/3_000_000_iterations/new_network").unwrap();
-//     let s2 = read_to_string("networks/new_network").unwrap();
-//     let mut total: f32 = 0.0;
-//     let mut updated: f32 = 0.0;
-//     for (a, b) in s1.lines().zip(s2.lines()) {
-//         match a.parse::<f32>() {
-//             Ok(a) => {
-//                 let b = b.parse::<f32>().unwrap();
-//                 if a != b {
-//                     updated += 1.0;
-//                 }
-//                 total += 1.0;
-//             }
-//             Err(_) => {}
-//         }
-//     }
-//     println!("changed {}; lines {}; % changed lines {}", updated, total, updated/total);
-// }
+mod diff;
+mod checksum;
+mod error;
+mod metadata;
+mod statistics;
+mod safetensors;
+mod numpy;
+mod mapped;
+mod codegen;
+mod training;
+mod dataset;
+mod evaluation;
+mod checkpoint;
+mod metrics;
+#[cfg(test)]
+mod test_util;
 
 // fn main() {
 //     let mut network = load("networks/3_000_000_iterations/after_learn_network");
@@ -39,36 +36,123 @@ mod image;
 // }
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let metrics_format = match args.iter().position(|x| x == "--metrics-json") {
+        Some(i) => {
+            args.remove(i);
+            MetricsFormat::JsonLines
+        }
+        None => MetricsFormat::Csv,
+    };
+    match args.get(1).map(|x| x.as_str()) {
+        // neural_network diff <network> <network> [dataset]
+        Some("diff") if args.len() >= 4 => {
+            let samples = args.get(4).map_or(vec![], |dataset| sample_inputs(dataset, 10));
+            match diff(&load(&args[2]), &load(&args[3]), &samples) {
+                Ok(result) => println!("{result}"),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        // neural_network codegen <network> <output.rs>
+        Some("codegen") if args.len() >= 4 => {
+            if let Err(e) = codegen::write_rust(&load(&args[2]), &args[3]) {
+                eprintln!("can't write {}: {e}", args[3]);
+                std::process::exit(1);
+            }
+        }
+        // neural_network confusion <network> <dataset> [output.csv]
+        Some("confusion") if args.len() >= 4 => {
+            let matrix = confusion_matrix_dataset(&load(&args[2]), &args[3]);
+            println!("{matrix}");
+            if let Some(path) = args.get(4) {
+                std::fs::write(path, matrix.to_csv()).unwrap();
+            }
+        }
+        // neural_network resume <checkpoint> [--metrics-json]
+        Some("resume") if args.len() >= 3 => {
+            let mut neural_network = NeuralNetwork::empty();
+            let config = training_config();
+            let validation = InMemoryDataset::load("verification_dataset");
+            let metrics = MetricsLog::append(metrics_file(metrics_format), metrics_format).unwrap();
+            let mut metrics = metrics_log(metrics, &validation, &config);
+            match resume_with_callbacks(&mut neural_network, &config, &args[2], &mut [&mut ProgressLogger, &mut metrics]) {
+                Ok(summary) => {
+                    println!("{summary}");
+                    save_with_metadata(&neural_network, &summary.metadata(), "after_learn_network");
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => train(metrics_format),
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+}
+
+// without arguments a new network is trained,
+// --metrics-json logs the metrics of train and resume as json lines instead of csv
+const USAGE: &str = "usage:
+    neural_network [--metrics-json]
+    neural_network diff <network> <network> [dataset]
+    neural_network codegen <network> <output.rs>
+    neural_network confusion <network> <dataset> [output.csv]
+    neural_network resume <checkpoint> [--metrics-json]";
+
+fn metrics_file(format: MetricsFormat) -> &'static str {
+    match format {
+        MetricsFormat::Csv => "networks/metrics.csv",
+        MetricsFormat::JsonLines => "networks/metrics.jsonl",
+    }
+}
+
+// the validation accuracy is in every 10th row
+fn metrics_log<'a>(metrics: MetricsLog<'a>, validation: &'a InMemoryDataset, config: &TrainingConfig) -> MetricsLog<'a> {
+    metrics.with_validation(validation, 10 * config.log_interval)
+}
+
+// the same for train and resume, a resumed training must use the config it was started with
+fn training_config() -> TrainingConfig {
+    TrainingConfig {
+        validation_dataset: Some("verification_dataset".to_string()),
+        early_stopping: Some(EarlyStopping { save_best: Some("networks/best_network".to_string()), ..Default::default() }),
+        checkpoint: Some("networks/checkpoint".to_string()),
+        ..Default::default()
+    }
+}
+
+fn train(metrics_format: MetricsFormat) {
     // let mut neural_network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 100, 10]);
     let mut neural_network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 800, 10]);
     // let mut neural_network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 1000, 800, 10]);
     let old_network = neural_network.clone();
     save(&neural_network, "new_network");
-    test_data(&mut neural_network);
+    test_data(&neural_network);
     println!("training...");
-    learn(&mut neural_network);
-    test_data(&mut neural_network);
-    save(&neural_network, "after_learn_network");
-
-    // let old_network = load("networks/new_network");
-    let mut total_biases = 0.0;
-    let mut total_weights = 0.0;
-    let mut changed_biases = 0.0;
-    let mut changed_weights = 0.0;
-    for i in 0..neural_network.weights.len() {
-        for j in 0..neural_network.weights[i].len() {
-            for k in 0..neural_network.weights[i][j].len() {
-                total_weights += 1.0;
-                changed_weights += (neural_network.weights[i][j][k] != old_network.weights[i][j][k]) as u32 as f64;
-            }
-            total_biases += 1.0;
-            changed_biases += (neural_network.biases[i][j] != old_network.biases[i][j]) as u32 as f64;
+    let config = training_config();
+    let validation = InMemoryDataset::load("verification_dataset");
+    let metrics = MetricsLog::create(metrics_file(metrics_format), metrics_format).unwrap();
+    let mut metrics = metrics_log(metrics, &validation, &config);
+    let summary = match learn_with_callbacks(&mut neural_network, &config, &mut [&mut ProgressLogger, &mut metrics]) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
         }
-    }
-    println!("*");
-    println!("biases; changed {}; total {}; {}%", changed_biases, total_biases, changed_biases / total_biases * 100.0);
-    println!("weights; changed {}; total {}; {}%", changed_weights, total_weights, changed_weights / total_weights * 100.0);
-    println!("TOTAL; changed {}; total {}; {}%", changed_biases + changed_weights, total_biases + total_weights, (changed_biases + changed_weights) / (total_biases + total_weights) * 100.0);
+    };
+    println!("{summary}");
+    test_data(&neural_network);
+    save_with_metadata(&neural_network, &summary.metadata(), "after_learn_network");
+    let samples = sample_inputs("verification_dataset", 10);
+    println!("{}", statistics(&neural_network, &samples));
+    println!("{}", diff(&old_network, &neural_network, &samples).unwrap());
 }
 
 // fn main() {
@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::path::Path;
+use memmap2::Mmap;
+use crate::error::ModelError;
+use crate::neural_network::NeuralNetwork;
+
+// a binary network used straight from the memory-mapped file, the values are never copied,
+// so processes that map the same file share its pages;
+// save() replaces files by renaming, so a mapped file keeps its old contents when the network is saved again,
+// but anything that writes into the file in place while it's mapped changes the weights under the running network
+pub struct MappedNetwork {
+    mmap: Mmap,
+    layers: Vec<u32>,
+    parameters: usize,
+    header_size: usize,
+    checksum: Option<u32>,
+}
+
+impl MappedNetwork {
+    // only the header is read, the values are loaded by the OS when they are first used
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ModelError> {
+        // the values are read as native f32
+        if cfg!(target_endian = "big") {
+            return Err(ModelError::InvalidHeader("memory-mapped networks need a little-endian target".to_string()));
+        }
+        let file = File::open(path)?;
+        // safety: the mapping is read-only, see the struct comment about files modified in place
+        let mmap = unsafe { Mmap::map(&file)? };
+        let (layers, header_size, checksum) = NeuralNetwork::parse_binary_header(&mmap)?;
+        // the map starts at a page boundary, so the values are aligned when the header size is
+        if header_size % align_of::<f32>() != 0 {
+            return Err(ModelError::InvalidHeader(format!("header size {header_size} isn't a multiple of 4")));
+        }
+        let parameters = NeuralNetwork::parameter_count(&layers)?;
+        Ok(MappedNetwork { mmap, layers, parameters, header_size, checksum })
+    }
+
+    // sizes of all layers including the input, for example [784, 800, 10]
+    pub fn layers(&self) -> &[u32] {
+        &self.layers
+    }
+
+    // all weights and biases in the order of NeuralNetwork::parameters()
+    pub fn values(&self) -> &[f32] {
+        let end = self.header_size + 4 * self.parameters;
+        // safety: every bit pattern is a valid f32 and open() checked the alignment and the size
+        let (prefix, values, suffix) = unsafe { self.mmap[self.header_size..end].align_to::<f32>() };
+        assert!(prefix.is_empty() && suffix.is_empty());
+        values
+    }
+
+    // reads the whole file, so it's not called by open()
+    pub fn verify_checksum(&self) -> Result<(), ModelError> {
+        match self.checksum {
+            Some(expected) => NeuralNetwork::check_checksum(expected, self.values()),
+            None => Ok(()),
+        }
+    }
+
+    // the same as NeuralNetwork::process, every neuron is its weights followed by its bias
+    pub fn process(&self, input: &[f32]) -> Vec<f32> {
+        assert_eq!(input.len(), self.layers[0] as usize, "the input must have one value per input neuron");
+        let mut values = self.values();
+        let mut prev = input.to_vec();
+        for layer in 1..self.layers.len() {
+            let inputs = self.layers[layer - 1] as usize;
+            let neurons = self.layers[layer] as usize;
+            let (current, rest) = values.split_at((inputs + 1) * neurons);
+            prev = current.chunks_exact(inputs + 1)
+                .map(|neuron| {
+                    let mut tmp = 0.0;
+                    for (weight, x) in neuron[..inputs].iter().zip(&prev) {
+                        tmp += weight * x;
+                    }
+                    NeuralNetwork::activation(tmp + neuron[inputs])
+                })
+                .collect();
+            values = rest;
+        }
+        prev
+    }
+
+    // a copy that can be trained, it fails like verify_checksum() when the values don't match the checksum
+    pub fn to_network(&self) -> Result<NeuralNetwork, ModelError> {
+        NeuralNetwork::try_deserialize_binary(&self.mmap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use crate::error::ModelError;
+    use crate::mapped::MappedNetwork;
+    use crate::neural_network::NeuralNetwork;
+    use crate::test_util::temp_file;
+
+    #[test]
+    fn test_mapped_process() {
+        let network = NeuralNetwork::new(&[5, 7, 10, 10]);
+        let path = temp_file("mapped_process", &network.serialize_binary());
+
+        let mapped = MappedNetwork::open(&path).unwrap();
+        let input = [0.1, 0.8, 0.0, 1.0, 0.5];
+
+        assert_eq!(mapped.layers(), &[5, 7, 10, 10]);
+        assert_eq!(mapped.values(), network.parameters());
+        assert_eq!(mapped.process(&input), network.process(&input));
+        assert!(mapped.verify_checksum().is_ok());
+        assert_eq!(mapped.to_network().unwrap(), network);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "the input must have one value per input neuron")]
+    fn test_mapped_process_short_input() {
+        let path = temp_file("mapped_short_input", &NeuralNetwork::new(&[5, 3]).serialize_binary());
+        let mapped = MappedNetwork::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        mapped.process(&[0.1, 0.8]);
+    }
+
+    #[test]
+    fn test_mapped_errors() {
+        let network = NeuralNetwork::new(&[2, 3, 1]);
+        let serialized = network.serialize_binary();
+        let truncated = temp_file("mapped_truncated", &serialized[..serialized.len() - 10]);
+        let mut corrupted = serialized.clone();
+        corrupted[30] ^= 1;
+        let corrupted = temp_file("mapped_corrupted", &corrupted);
+
+        assert!(matches!(MappedNetwork::open(&truncated), Err(ModelError::UnexpectedEof { .. })));
+        assert!(matches!(MappedNetwork::open(&corrupted).unwrap().verify_checksum(), Err(ModelError::ChecksumMismatch { .. })));
+        assert!(matches!(MappedNetwork::open(&corrupted).unwrap().to_network(), Err(ModelError::ChecksumMismatch { .. })));
+        fs::remove_file(truncated).unwrap();
+        fs::remove_file(corrupted).unwrap();
+    }
+}
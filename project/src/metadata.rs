@@ -0,0 +1,128 @@
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, Local};
+use crate::error::ModelError;
+
+// how a network was made, saved as "key value" lines before the header of text networks
+// and in the header of binary networks, unknown keys are ignored when reading
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub created: Option<DateTime<FixedOffset>>,
+    pub activation: Option<String>,
+    pub loss: Option<String>,
+    pub optimizer: Option<String>,
+    pub learning_rate: Option<f32>,
+    pub training_duration: Option<Duration>,
+    pub iterations: Option<u64>,
+    pub dataset: Option<String>,
+    pub training_samples: Option<u64>,
+    pub validation_samples: Option<u64>,
+    pub validation_accuracy: Option<f32>,
+}
+
+impl Metadata {
+    // created now, with the activation, loss and optimizer this crate uses
+    pub fn new() -> Self {
+        Metadata {
+            created: Some(Local::now().fixed_offset()),
+            activation: Some("sigmoid".to_string()),
+            loss: Some("squared error".to_string()),
+            optimizer: Some("sgd".to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut push = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                result.push(format!("{key} {value}"));
+            }
+        };
+        push("created", self.created.map(|x| x.to_rfc3339()));
+        push("activation", self.activation.clone());
+        push("loss", self.loss.clone());
+        push("optimizer", self.optimizer.clone());
+        push("learning_rate", self.learning_rate.map(|x| x.to_string()));
+        push("training_duration", self.training_duration.map(|x| x.as_secs_f64().to_string()));
+        push("iterations", self.iterations.map(|x| x.to_string()));
+        push("dataset", self.dataset.clone());
+        push("training_samples", self.training_samples.map(|x| x.to_string()));
+        push("validation_samples", self.validation_samples.map(|x| x.to_string()));
+        push("validation_accuracy", self.validation_accuracy.map(|x| x.to_string()));
+        result
+    }
+
+    // reads "key value" lines, the first line number is used in errors
+    pub fn parse<'a>(lines: impl Iterator<Item = &'a str>, first_line: usize) -> Result<Self, ModelError> {
+        let mut result = Metadata::default();
+        for (i, line) in lines.enumerate() {
+            let line_number = first_line + i;
+            let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            let error = || ModelError::UnparsableValue { line: line_number, value: line.to_string() };
+            match key {
+                "created" => result.created = Some(DateTime::parse_from_rfc3339(value).map_err(|_| error())?),
+                "activation" => result.activation = Some(value.to_string()),
+                "loss" => result.loss = Some(value.to_string()),
+                "optimizer" => result.optimizer = Some(value.to_string()),
+                "learning_rate" => result.learning_rate = Some(value.parse().map_err(|_| error())?),
+                "training_duration" => {
+                    let seconds: f64 = value.parse().map_err(|_| error())?;
+                    result.training_duration = Some(Duration::try_from_secs_f64(seconds).map_err(|_| error())?);
+                }
+                "iterations" => result.iterations = Some(value.parse().map_err(|_| error())?),
+                "dataset" => result.dataset = Some(value.to_string()),
+                "training_samples" => result.training_samples = Some(value.parse().map_err(|_| error())?),
+                "validation_samples" => result.validation_samples = Some(value.parse().map_err(|_| error())?),
+                "validation_accuracy" => result.validation_accuracy = Some(value.parse().map_err(|_| error())?),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use chrono::DateTime;
+    use crate::error::ModelError;
+    use crate::metadata::Metadata;
+
+    #[test]
+    fn test_to_lines_parse() {
+        let metadata = Metadata {
+            created: Some(DateTime::parse_from_rfc3339("2025-03-01T10:20:30+01:00").unwrap()),
+            learning_rate: Some(0.5),
+            training_duration: Some(Duration::from_secs(300)),
+            iterations: Some(12000),
+            dataset: Some("training_data".to_string()),
+            validation_accuracy: Some(0.93),
+            ..Metadata::new()
+        };
+
+        let lines = metadata.to_lines();
+        let parsed = Metadata::parse(lines.iter().map(|x| x.as_str()), 1).unwrap();
+
+        assert_eq!(lines[0], "created 2025-03-01T10:20:30+01:00");
+        assert_eq!(lines[1], "activation sigmoid");
+        assert_eq!(parsed, metadata);
+    }
+
+    #[test]
+    fn test_parse_ignores_unknown_keys() {
+        let parsed = Metadata::parse(["author someone", "iterations 10"].into_iter(), 1).unwrap();
+
+        assert_eq!(parsed, Metadata { iterations: Some(10), ..Default::default() });
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let parsed = Metadata::parse(["iterations 10", "learning_rate fast"].into_iter(), 3);
+
+        assert!(matches!(parsed, Err(ModelError::UnparsableValue { line: 4, .. })));
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::time::Instant;
+use crate::dataset::Dataset;
+use crate::evaluation::evaluate;
+use crate::training::{TrainingCallback, TrainingProgress, TrainingSummary};
+
+// the columns of the csv and the keys of every json line, in this order, new columns are only ever added at the end
+pub const METRICS_COLUMNS: [&str; 8] = [
+    "timestamp", "iteration", "epoch", "samples", "loss", "learning_rate", "samples_per_second", "validation_accuracy",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricsFormat {
+    // a header line, missing values are empty
+    Csv,
+    // one object per line, missing values are null
+    JsonLines,
+}
+
+// a row every log_interval steps of the config and one at the end of the training,
+// loss is the mean loss since the previous row, epoch the number of finished epochs,
+// every row is flushed, so the file can be read while the training runs,
+// a row that can't be written stops the training, error() tells why
+pub struct MetricsLog<'a> {
+    file: BufWriter<File>,
+    format: MetricsFormat,
+    // the accuracy on it is measured every validation_interval steps, in the rows of those steps
+    validation: Option<(&'a dyn Dataset, u64)>,
+    last_row: Option<(u64, u64, Instant)>,
+    start: Instant,
+    // after append the samples also count the interrupted run, so the first row has no throughput
+    resumed: bool,
+    error: Option<std::io::Error>,
+}
+
+impl<'a> MetricsLog<'a> {
+    pub fn create(path: impl AsRef<Path>, format: MetricsFormat) -> std::io::Result<Self> {
+        let mut result = Self::new(File::create(path)?, format);
+        result.write_header()?;
+        Ok(result)
+    }
+
+    // for a resumed training, the header is only written to an empty file
+    pub fn append(path: impl AsRef<Path>, format: MetricsFormat) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        let mut result = Self { resumed: true, ..Self::new(file, format) };
+        if empty {
+            result.write_header()?;
+        }
+        Ok(result)
+    }
+
+    // validation_interval should be a multiple of log_interval, otherwise the accuracy is never in a row
+    pub fn with_validation(mut self, dataset: &'a dyn Dataset, validation_interval: u64) -> Self {
+        self.validation = Some((dataset, validation_interval));
+        self
+    }
+
+    fn new(file: File, format: MetricsFormat) -> Self {
+        MetricsLog { file: BufWriter::new(file), format, validation: None, last_row: None, start: Instant::now(), resumed: false, error: None }
+    }
+
+    // the first error writing a row
+    pub fn error(&self) -> Option<&std::io::Error> {
+        self.error.as_ref()
+    }
+
+    fn check(&mut self, result: std::io::Result<()>) -> ControlFlow<()> {
+        match result {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                println!("{}; the metrics can't be written: {e}", chrono::Local::now());
+                self.error.get_or_insert(e);
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if self.format == MetricsFormat::Csv {
+            writeln!(self.file, "{}", METRICS_COLUMNS.join(","))?;
+        }
+        self.file.flush()
+    }
+
+    fn write_row(&mut self, progress: &TrainingProgress, validation_accuracy: Option<f32>) -> std::io::Result<()> {
+        if self.last_row.is_some_and(|(iteration, _, _)| iteration == progress.iteration) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let (samples, time) = self.last_row.map_or((0, self.start), |(_, samples, time)| (samples, time));
+        let seconds = now.duration_since(time).as_secs_f64();
+        let known = self.last_row.is_some() || !self.resumed;
+        let throughput = (known && seconds > 0.0).then(|| (progress.samples.saturating_sub(samples)) as f64 / seconds);
+        self.last_row = Some((progress.iteration, progress.samples, now));
+
+        let values = [
+            Some(chrono::Local::now().to_rfc3339()),
+            Some(progress.iteration.to_string()),
+            Some(progress.epoch.to_string()),
+            Some(progress.samples.to_string()),
+            number(progress.loss),
+            number(progress.learning_rate),
+            throughput.and_then(number),
+            validation_accuracy.and_then(number),
+        ];
+        match self.format {
+            MetricsFormat::Csv => {
+                let row: Vec<String> = values.into_iter().map(Option::unwrap_or_default).collect();
+                writeln!(self.file, "{}", row.join(","))?;
+            }
+            MetricsFormat::JsonLines => {
+                let fields: Vec<String> = METRICS_COLUMNS.iter().zip(values).enumerate()
+                    .map(|(i, (column, value))| match value {
+                        // the timestamp is the only string
+                        Some(value) if i == 0 => format!("\"{column}\":\"{value}\""),
+                        Some(value) => format!("\"{column}\":{value}"),
+                        None => format!("\"{column}\":null"),
+                    })
+                    .collect();
+                writeln!(self.file, "{{{}}}", fields.join(","))?;
+            }
+        }
+        self.file.flush()
+    }
+}
+
+// neither csv readers nor json have a common spelling of nan and infinity, so they are missing values
+// f32 values are printed as f32, 0.1 and not 0.10000000149011612
+fn number<T: Into<f64> + ToString + Copy>(x: T) -> Option<String> {
+    x.into().is_finite().then(|| x.to_string())
+}
+
+impl TrainingCallback for MetricsLog<'_> {
+    fn on_interval(&mut self, progress: &TrainingProgress) -> ControlFlow<()> {
+        let validation_accuracy = match self.validation {
+            Some((dataset, interval)) if interval > 0 && progress.iteration.is_multiple_of(interval) => {
+                Some(evaluate(progress.network, dataset).accuracy)
+            }
+            _ => None,
+        };
+        let result = self.write_row(progress, validation_accuracy);
+        self.check(result)
+    }
+
+    fn on_training_end(&mut self, progress: &TrainingProgress, summary: &TrainingSummary) {
+        let result = self.write_row(progress, summary.validation_accuracy);
+        let _ = self.check(result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::ops::ControlFlow;
+    use crate::dataset::InMemoryDataset;
+    use crate::image::{HEIGHT, WIDTH};
+    use crate::metrics::{MetricsFormat, MetricsLog, METRICS_COLUMNS};
+    use crate::neural_network::NeuralNetwork;
+    use crate::test_util::temp_path;
+    use crate::training::{TrainingCallback, TrainingProgress};
+
+    fn log(format: MetricsFormat, name: &str) -> Vec<String> {
+        let path = temp_path(name);
+        let network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 3, 10]);
+        let dataset = InMemoryDataset::from_samples(&[vec![0.0; WIDTH * HEIGHT], vec![1.0; WIDTH * HEIGHT]], &[0, 1]);
+        let progress = |iteration| TrainingProgress { iteration, epoch: iteration / 4, samples: 2 * iteration, loss: 0.25, learning_rate: 0.1, network: &network };
+
+        let mut metrics = MetricsLog::create(&path, format).unwrap().with_validation(&dataset, 4);
+        assert_eq!(metrics.on_interval(&progress(2)), ControlFlow::Continue(()));
+        assert_eq!(metrics.on_interval(&progress(4)), ControlFlow::Continue(()));
+        drop(metrics);
+        // a resumed training continues the file
+        let mut metrics = MetricsLog::append(&path, format).unwrap();
+        assert_eq!(metrics.on_interval(&progress(6)), ControlFlow::Continue(()));
+        assert_eq!(metrics.on_interval(&progress(8)), ControlFlow::Continue(()));
+        drop(metrics);
+
+        let result = fs::read_to_string(&path).unwrap().lines().map(String::from).collect();
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_metrics_csv() {
+        let lines = log(MetricsFormat::Csv, "metrics_csv");
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], METRICS_COLUMNS.join(","));
+        let rows: Vec<Vec<&str>> = lines[1..].iter().map(|line| line.split(',').collect()).collect();
+        assert!(rows.iter().all(|row| row.len() == METRICS_COLUMNS.len()));
+        assert_eq!(rows[0][1..6], ["2", "0", "4", "0.25", "0.1"]);
+        assert_eq!(rows[0][7], "");
+        // the network is random, but the accuracy of 2 samples is one of these
+        assert!(["0", "0.5", "1"].contains(&rows[1][7]));
+        assert_eq!(rows[2][1..4], ["6", "1", "12"]);
+        assert_eq!(rows[2][7], "");
+        // the samples of the first row include the ones before the resume
+        assert_eq!(rows[2][6], "");
+        assert!(rows[3][6].parse::<f64>().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_metrics_json_lines() {
+        let lines = log(MetricsFormat::JsonLines, "metrics_json_lines");
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("{\"timestamp\":\""));
+        assert!(lines[0].contains(",\"iteration\":2,\"epoch\":0,\"samples\":4,\"loss\":0.25,\"learning_rate\":0.1,\"samples_per_second\":"));
+        assert!(lines[0].ends_with(",\"validation_accuracy\":null}"));
+        assert!(!lines[1].ends_with(",\"validation_accuracy\":null}"));
+        assert!(lines[2].contains(",\"samples_per_second\":null,"));
+        assert!(!lines[3].contains(",\"samples_per_second\":null,"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_metrics_write_error() {
+        let network = NeuralNetwork::new(&[2, 2]);
+        let progress = TrainingProgress { iteration: 1, epoch: 0, samples: 1, loss: 0.25, learning_rate: 0.1, network: &network };
+        // every write to /dev/full fails with "no space left on device"
+        let mut metrics = MetricsLog::new(File::options().write(true).open("/dev/full").unwrap(), MetricsFormat::Csv);
+
+        assert!(metrics.error().is_none());
+        assert_eq!(metrics.on_interval(&progress), ControlFlow::Break(()));
+        assert!(metrics.error().is_some());
+    }
+}
@@ -1,73 +1,375 @@
 use std::fs;
-use std::fs::{read, read_to_string, write, File};
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::fs::{read, write, File};
+use std::io::{BufRead, BufReader, Read};
+use std::ops::ControlFlow;
 use std::time::{Duration, Instant};
-use rand::random;
-use rand::seq::IteratorRandom;
-use crate::image::{get_training_data, get_training_data_path};
-use crate::neural_network::NeuralNetwork;
+use rand::{random, Rng};
+use rand::seq::{IndexedRandom, IteratorRandom, SliceRandom};
+use crate::checkpoint::{from_checkpoint, to_checkpoint};
+use crate::dataset::{Dataset, InMemoryDataset};
+use crate::error::ModelError;
+use crate::evaluation::{confusion_matrix, evaluate, ConfusionMatrix, EvalReport};
+use crate::image::get_training_data_path;
+use crate::mapped::MappedNetwork;
+use crate::metadata::Metadata;
+use crate::neural_network::{NeuralNetwork, BINARY_MAGIC};
+use crate::numpy::{from_npz, to_npz};
+use crate::network_math;
+use crate::safetensors::{from_safetensors, to_safetensors};
+use crate::training::{BestNetwork, EarlyStopping, EpochSummary, ProgressLogger, Sampling, StopCondition, TrainingCallback, TrainingConfig, TrainingProgress, TrainingState, TrainingSummary};
 
-pub fn test_data(neural_network: &mut NeuralNetwork) {
-    check_digits(neural_network, "verification_dataset");
-    check_digits(neural_network, "dataset");
-    check_digits(neural_network, "training_data");
+// evaluates the network on every sample of the three datasets
+pub fn test_data(neural_network: &NeuralNetwork) {
+    for dataset in ["verification_dataset", "dataset", "training_data"] {
+        println!("{dataset}\n{}", evaluate_dataset(neural_network, dataset));
+    }
 }
 
-fn check_digits(neural_network: &mut NeuralNetwork, dataset: &str) {
-    for digit in 0..10 {
-        let (input, _target) = get_training_data_path(&random_file(&format!("{dataset}/{digit}/{digit}/")), digit);
-        let result = neural_network.process_mutable(&input);
-        // println!("INPUT: {:?}", &input[260..270]);
-        println!("{dataset} {digit}: {result:?}");
+// {catalog}/{digit}/{digit}/*.png, every image is processed once
+pub fn evaluate_dataset(neural_network: &NeuralNetwork, catalog: &str) -> EvalReport {
+    evaluate(neural_network, &InMemoryDataset::load(catalog))
+}
+
+pub fn confusion_matrix_dataset(neural_network: &NeuralNetwork, catalog: &str) -> ConfusionMatrix {
+    confusion_matrix(neural_network, &InMemoryDataset::load(catalog))
+}
+
+// the datasets are loaded from the paths in the config,
+// returns how the network was trained, summary.metadata() can be saved with the network
+pub fn learn(neural_network: &mut NeuralNetwork, config: &TrainingConfig) -> Result<TrainingSummary, ModelError> {
+    let (training, validation) = load_datasets(config)?;
+    learn_with_datasets(neural_network, config, &training, validation.as_ref().map(|x| x as &dyn Dataset), &mut [&mut ProgressLogger])
+}
+
+// the same as learn, but with the callbacks instead of the progress lines, add ProgressLogger to keep them
+pub fn learn_with_callbacks(neural_network: &mut NeuralNetwork, config: &TrainingConfig, callbacks: &mut [&mut dyn TrainingCallback]) -> Result<TrainingSummary, ModelError> {
+    let (training, validation) = load_datasets(config)?;
+    learn_with_datasets(neural_network, config, &training, validation.as_ref().map(|x| x as &dyn Dataset), callbacks)
+}
+
+// continues the training saved as a checkpoint, the network is replaced by the one in the checkpoint,
+// the config should be the one of the interrupted training, only the stop condition may be different
+pub fn resume(neural_network: &mut NeuralNetwork, config: &TrainingConfig, checkpoint: &str) -> Result<TrainingSummary, ModelError> {
+    resume_with_callbacks(neural_network, config, checkpoint, &mut [&mut ProgressLogger])
+}
+
+pub fn resume_with_callbacks(neural_network: &mut NeuralNetwork, config: &TrainingConfig, checkpoint: &str, callbacks: &mut [&mut dyn TrainingCallback]) -> Result<TrainingSummary, ModelError> {
+    let (network, state) = from_checkpoint(&read(checkpoint)?)?;
+    let (training, validation) = load_datasets(config)?;
+    if state.order.len() != training.len() {
+        return Err(ModelError::ShapeMismatch { name: "training dataset".to_string(), expected: vec![state.order.len()], actual: vec![training.len()] });
     }
+    *neural_network = network;
+    training_loop(neural_network, config, &training, validation.as_ref().map(|x| x as &dyn Dataset), Some(state), callbacks, |network, batch, training_rate| {
+        network.training_batch(batch, training_rate)
+    })
 }
 
-pub fn learn(neural_network: &mut NeuralNetwork) {
-    //todo add random training rate, sometimes high, for example 10.0, in rare cases even 100.0, often casual like 10.0 or 1.0
+// the same as learn_with_callbacks, but the dataset paths in the config are ignored
+pub fn learn_with_datasets(neural_network: &mut NeuralNetwork, config: &TrainingConfig, training: &dyn Dataset, validation: Option<&dyn Dataset>, callbacks: &mut [&mut dyn TrainingCallback]) -> Result<TrainingSummary, ModelError> {
+    training_loop(neural_network, config, training, validation, None, callbacks, |network, batch, training_rate| network.training_batch(batch, training_rate))
+}
 
-    // let training_rate = 1.0;
-    // let training_rate = 10.0;
-    // let training_rate = 10.0;
-    // let training_rate = 0.001;
-    // let training_rate = 1.0;
-    // let training_rate = 0.01;
-    // let training_rate = 0.1;
-    let training_rate = 0.5;
+// trains a (usually smaller) student network to mimic the teacher, the loss is alpha times the squared error
+// between the student and the teacher outputs, both softened by the temperature, plus 1 - alpha times
+// the usual squared error against the labels; the soft part is multiplied by temperature^2, so its gradients
+// keep their size when the temperature changes; alpha = 1.0 means only the teacher outputs are used,
+// alpha = 0.0 means only the labels are used, the reported loss and accuracy are the ones against the labels
+pub fn distill(student: &mut NeuralNetwork, teacher: &NeuralNetwork, temperature: f32, alpha: f32, config: &TrainingConfig) -> Result<TrainingSummary, ModelError> {
+    let (training, validation) = load_datasets(config)?;
+    training_loop(student, config, &training, validation.as_ref().map(|x| x as &dyn Dataset), None, &mut [&mut ProgressLogger], |student, batch, training_rate| {
+        let soft_targets: Vec<Vec<f32>> = batch.iter().map(|(input, _)| teacher.soft_targets(input, temperature)).collect();
+        student.training_batch_with(batch, training_rate, |i, logits, outputs| {
+            logits.iter().zip(outputs).zip(soft_targets[i].iter().zip(batch[i].1))
+                .map(|((&logit, &a), (&soft_target, &y))| {
+                    let soft = NeuralNetwork::activation(logit / temperature);
+                    // d/dlogit of the soft squared error is (soft - soft_target) * soft * (1 - soft) / temperature
+                    alpha * temperature * (soft - soft_target) * soft * (1.0 - soft) + (1.0 - alpha) * (a - y) * a * (1.0 - a)
+                })
+                .collect()
+        })
+    })
+}
 
-    // let duration = Duration::from_secs(60 * 60 * 10);
-    let duration = Duration::from_secs(60 * 5);
+fn load_datasets(config: &TrainingConfig) -> Result<(InMemoryDataset, Option<InMemoryDataset>), ModelError> {
+    println!("{}; loading {}", chrono::Local::now(), config.training_dataset);
+    let training = InMemoryDataset::try_load(&config.training_dataset)?;
+    let validation = config.validation_dataset.as_ref().map(|dataset| InMemoryDataset::try_load(dataset)).transpose()?;
+    Ok((training, validation))
+}
 
-    let mut i = 0;
-    let time = Instant::now();
+// the training step gets the network, a batch of (input, target) and the training rate and returns the outputs before the step,
+// the loss and the accuracy are measured against the targets of the batch, a new training starts without a state,
+// a config that can't be trained with is an error before the first step
+fn training_loop(
+    neural_network: &mut NeuralNetwork,
+    config: &TrainingConfig,
+    dataset: &dyn Dataset,
+    validation: Option<&dyn Dataset>,
+    state: Option<TrainingState>,
+    callbacks: &mut [&mut dyn TrainingCallback],
+    mut training_step: impl FnMut(&mut NeuralNetwork, &[(&[f32], &[f32])], f32) -> Vec<Vec<f32>>,
+) -> Result<TrainingSummary, ModelError> {
+    // no sample means no batch, and every loss and accuracy would be divided by 0
+    if dataset.is_empty() {
+        return Err(ModelError::InvalidConfig("the training dataset is empty".to_string()));
+    }
+    let early_stopping = match (&config.early_stopping, validation) {
+        (Some(early_stopping), Some(validation)) => Some((early_stopping, validation)),
+        (Some(_), None) => return Err(ModelError::InvalidConfig("early stopping needs a validation dataset".to_string())),
+        (None, _) => None,
+    };
+    let mut state = state.unwrap_or_else(|| TrainingState::new(config.seed.unwrap_or_else(random), dataset.len()));
+    let mut sampler = Sampler::new(dataset.labels(), config.sampling);
+    (sampler.order, sampler.position) = (state.order.clone(), state.position);
+    let training_samples = dataset.len() as u64;
+    let batch_size = config.batch_size.max(1);
 
-    let minutes = duration.as_secs_f32() / 60.0;
-    let msg = match minutes {
-        x if x > 60.0 => format!("{} hours", minutes / 60.0),
-        x if x > 1.0 => format!("{} minutes", minutes),
-        _ => format!("{:?}", duration)
+    let msg = match config.stop {
+        StopCondition::Duration(duration) => {
+            let minutes = duration.as_secs_f32() / 60.0;
+            match minutes {
+                x if x > 60.0 => format!("{} hours", minutes / 60.0),
+                x if x > 1.0 => format!("{} minutes", minutes),
+                _ => format!("{:?}", duration)
+            }
+        }
+        StopCondition::Iterations(iterations) => format!("{iterations} iterations"),
+        StopCondition::Epochs(epochs) => format!("{epochs} epochs"),
     };
     println!("{}; the training will last {}", chrono::Local::now(), msg);
-    while time.elapsed() < duration {
-    // for i in 0..10_000 {
-    // for i in 0..100 {
-    // for i in 0..500 {
-    // for i in 0..60_000 {
-    // for i in 0..300_000 {
-    // for i in 0..1_000_000 {
-    // for i in 0..3_000_000 {
-        if i % 500 == 0 {
-            println!("{}; iteration {};", chrono::Local::now(), i);
-        }
-        let digit: u8 = random::<u8>() % 10;
-        // let index: u16 = random::<u16>() % 10773;
-        // let (input, target) = get_training_data("dataset", digit, index);
-        let file = random_file(&format!("training_data/{digit}/{digit}/"));
-        let (input, target) = get_training_data_path(&file, digit);
-        neural_network.training_step(&input, &target, training_rate);
-        i += 1;
-    }
-    println!("{}; iteration {};", chrono::Local::now(), i);
+    if state.iteration > 0 {
+        println!("{}; resuming at iteration {}", chrono::Local::now(), state.iteration);
+    }
+
+    let mut learning_rate = config.learning_rate;
+    let mut epoch_time = Instant::now();
+    let mut stopped_early = false;
+    let time = Instant::now();
+    while !match config.stop {
+        StopCondition::Duration(duration) => state.elapsed + time.elapsed() >= duration,
+        StopCondition::Iterations(iterations) => state.iteration >= iterations,
+        StopCondition::Epochs(epochs) => state.samples >= epochs * training_samples,
+    } {
+        let batch: Vec<(&[f32], &[f32])> = sampler.next_batch(&mut state.rng, batch_size).into_iter().map(|i| dataset.get(i)).collect();
+        learning_rate = config.schedule.rate(config.learning_rate, state.iteration, &mut state.rng);
+        let outputs = training_step(neural_network, &batch, learning_rate);
+
+        let batch_loss: f32 = outputs.iter().zip(&batch).map(|(output, (_, target))| network_math::squared_error(output, target)).sum();
+        let correct = outputs.iter().zip(&batch)
+            .filter(|(output, (_, target))| network_math::argmax(output) == network_math::argmax(target))
+            .count();
+        // the last batch of an epoch may be smaller, but none is empty
+        let batch_loss_mean = batch_loss / batch.len().max(1) as f32;
+        state.loss_sum += batch_loss_mean;
+        state.loss_steps += 1;
+        state.iteration += 1;
+        state.samples += batch.len() as u64;
+        let i = state.iteration;
+        let mut progress = TrainingProgress {
+            iteration: i,
+            epoch: state.epochs.len() as u64,
+            samples: state.samples,
+            loss: batch_loss_mean,
+            learning_rate,
+            network: neural_network,
+        };
+        let mut stop = notify(callbacks, |callback| callback.on_step_end(&progress));
+        if config.log_interval > 0 && i.is_multiple_of(config.log_interval) {
+            state.loss = state.loss_sum / state.loss_steps as f32;
+            (state.loss_sum, state.loss_steps) = (0.0, 0);
+            progress.loss = state.loss;
+            stop |= notify(callbacks, |callback| callback.on_interval(&progress));
+        }
+
+        state.epoch_samples += batch.len() as u64;
+        state.epoch_loss += batch_loss;
+        state.epoch_correct += correct as u64;
+        if state.epoch_samples >= training_samples {
+            let epoch = EpochSummary {
+                epoch: state.epochs.len() as u64 + 1,
+                samples: state.epoch_samples,
+                loss: state.epoch_loss / state.epoch_samples as f32,
+                accuracy: state.epoch_correct as f32 / state.epoch_samples as f32,
+                duration: state.epoch_elapsed + epoch_time.elapsed(),
+            };
+            progress.epoch += 1;
+            progress.loss = epoch.loss;
+            stop |= notify(callbacks, |callback| callback.on_epoch_end(&progress, &epoch));
+            state.epochs.push(epoch);
+            (state.epoch_samples, state.epoch_loss, state.epoch_correct, state.epoch_elapsed) = (0, 0.0, 0, Duration::ZERO);
+            epoch_time = Instant::now();
+        }
+
+        if let Some((early_stopping, validation)) = early_stopping
+            && i.is_multiple_of(early_stopping.interval.max(1))
+            && !check_best(neural_network, config, early_stopping, validation, i, &mut state.best) {
+            stopped_early = true;
+            break;
+        }
+
+        if let Some(path) = &config.checkpoint
+            && config.checkpoint_interval > 0
+            && i.is_multiple_of(config.checkpoint_interval) {
+            let checkpoint = TrainingState {
+                elapsed: state.elapsed + time.elapsed(),
+                epoch_elapsed: state.epoch_elapsed + epoch_time.elapsed(),
+                order: sampler.order.clone(),
+                position: sampler.position,
+                ..state.clone()
+            };
+            write_atomic(path, &to_checkpoint(neural_network, &checkpoint)).unwrap();
+            println!("{}; iteration {}; checkpoint saved as {};", chrono::Local::now(), i, path);
+        }
+
+        if stop {
+            println!("{}; iteration {}; stopped by a callback;", chrono::Local::now(), i);
+            stopped_early = true;
+            break;
+        }
+    }
+    let i = state.iteration;
+    if let Some((early_stopping, validation)) = early_stopping {
+        // the steps after the last evaluation count too
+        if !stopped_early && !i.is_multiple_of(early_stopping.interval.max(1)) {
+            check_best(neural_network, config, early_stopping, validation, i, &mut state.best);
+        }
+        if let Some(network) = state.best.as_mut().and_then(|best| best.network.take()) {
+            println!("{}; restoring the network of iteration {};", chrono::Local::now(), state.best.as_ref().unwrap().iteration);
+            *neural_network = network;
+        }
+    }
+    if state.loss_steps > 0 {
+        state.loss = state.loss_sum / state.loss_steps as f32;
+    }
+    let mut summary = TrainingSummary {
+        config: config.clone(),
+        seed: state.seed,
+        iterations: i,
+        samples: state.samples,
+        training_samples,
+        duration: state.elapsed + time.elapsed(),
+        loss: state.loss,
+        epochs: state.epochs,
+        validation_samples: None,
+        validation_accuracy: None,
+        best_iteration: state.best.as_ref().map(|best| best.iteration),
+        best_accuracy: state.best.as_ref().map(|best| best.accuracy),
+        stopped_early,
+    };
+    if let Some(validation) = validation {
+        validate(neural_network, validation, &mut summary);
+    }
+    let progress = TrainingProgress {
+        iteration: i,
+        epoch: summary.epochs.len() as u64,
+        samples: summary.samples,
+        loss: summary.loss,
+        learning_rate,
+        network: neural_network,
+    };
+    for callback in callbacks.iter_mut() {
+        callback.on_training_end(&progress, &summary);
+    }
+    Ok(summary)
+}
+
+// every callback is called, also after one of them asked to stop, returns if any did
+fn notify(callbacks: &mut [&mut dyn TrainingCallback], mut hook: impl FnMut(&mut dyn TrainingCallback) -> ControlFlow<()>) -> bool {
+    let mut stop = false;
+    for callback in callbacks.iter_mut() {
+        stop |= hook(*callback).is_break();
+    }
+    stop
+}
+
+// evaluates the network and keeps it if it is the best so far, returns false when the patience ran out
+fn check_best(neural_network: &NeuralNetwork, config: &TrainingConfig, early_stopping: &EarlyStopping, validation: &dyn Dataset, iteration: u64, best: &mut Option<BestNetwork>) -> bool {
+    let report = evaluate(neural_network, validation);
+    println!("{}; iteration {}; validation accuracy {};", chrono::Local::now(), iteration, report.accuracy);
+    match best {
+        Some(best) if report.accuracy <= best.accuracy + early_stopping.min_delta => {
+            best.stale += 1;
+            best.stale < early_stopping.patience
+        }
+        _ => {
+            if let Some(path) = &early_stopping.save_best {
+                let metadata = Metadata {
+                    learning_rate: Some(config.learning_rate),
+                    iterations: Some(iteration),
+                    dataset: Some(config.training_dataset.clone()),
+                    validation_samples: Some(report.samples as u64),
+                    validation_accuracy: Some(report.accuracy),
+                    ..Metadata::new()
+                };
+                write_atomic(path, &neural_network.serialize_binary_with_metadata(&metadata)).unwrap();
+            }
+            let network = early_stopping.restore_best.then(|| neural_network.clone());
+            *best = Some(BestNetwork { iteration, accuracy: report.accuracy, network, stale: 0 });
+            true
+        }
+    }
+}
+
+// picks the indexes of the samples of the batches
+struct Sampler {
+    sampling: Sampling,
+    // indexes of the samples of every digit
+    digits: Vec<Vec<usize>>,
+    // every sample of the dataset, shuffled at the start of every epoch
+    order: Vec<usize>,
+    position: usize,
+}
+
+impl Sampler {
+    fn new(labels: &[u8], sampling: Sampling) -> Self {
+        let mut digits: Vec<Vec<usize>> = vec![Vec::new(); 10];
+        for (i, &label) in labels.iter().enumerate() {
+            digits[label as usize].push(i);
+        }
+        Sampler { sampling, digits, order: (0..labels.len()).collect(), position: labels.len() }
+    }
+
+    // with shuffled sampling the last batch of an epoch may be smaller, so the next batch starts a new epoch
+    fn next_batch(&mut self, rng: &mut impl Rng, batch_size: usize) -> Vec<usize> {
+        match self.sampling {
+            Sampling::Random => (0..batch_size)
+                .map(|_| {
+                    let digit: usize = rng.random_range(0..10);
+                    *self.digits[digit].choose(rng).unwrap_or_else(|| panic!("no training samples of digit {digit}"))
+                })
+                .collect(),
+            Sampling::Shuffled => {
+                if self.position == self.order.len() {
+                    self.order.shuffle(rng);
+                    self.position = 0;
+                }
+                let end = (self.position + batch_size).min(self.order.len());
+                let batch = self.order[self.position..end].to_vec();
+                self.position = end;
+                batch
+            }
+        }
+    }
+}
+
+// accuracy on every sample of the validation dataset
+fn validate(neural_network: &NeuralNetwork, dataset: &dyn Dataset, summary: &mut TrainingSummary) {
+    let report = evaluate(neural_network, dataset);
+    summary.validation_samples = Some(report.samples as u64);
+    summary.validation_accuracy = Some(report.accuracy);
+}
+
+// random inputs from the dataset, the same number of samples for every digit
+pub fn sample_inputs(dataset: &str, per_digit: usize) -> Vec<Vec<f32>> {
+    let mut result = Vec::new();
+    for digit in 0..10 {
+        for _ in 0..per_digit {
+            let (input, _target) = get_training_data_path(&random_file(&format!("{dataset}/{digit}/{digit}/")), digit);
+            result.push(input.to_vec());
+        }
+    }
+    result
 }
 
 fn random_file(path: &str) -> String {
@@ -77,13 +379,117 @@ fn random_file(path: &str) -> String {
 }
 
 pub fn save(neural_network: &NeuralNetwork, name: &str) {
-    let serialized = neural_network.serialize();
-    write(format!("networks/{name}"), serialized).unwrap();
+    save_with_metadata(neural_network, &Metadata::new(), name);
+}
+
+pub fn save_with_metadata(neural_network: &NeuralNetwork, metadata: &Metadata, name: &str) {
+    let serialized = neural_network.serialize_binary_with_metadata(metadata);
+    write_atomic(&format!("networks/{name}"), &serialized).unwrap();
+}
+
+pub fn save_text(neural_network: &NeuralNetwork, name: &str) {
+    let serialized = neural_network.serialize_with_metadata(&Metadata::new());
+    write_atomic(&format!("networks/{name}"), serialized.as_bytes()).unwrap();
+}
+
+#[cfg(feature = "serde")]
+pub fn save_json(neural_network: &NeuralNetwork, name: &str) {
+    let serialized = serde_json::to_string(neural_network).unwrap();
+    write_atomic(&format!("networks/{name}"), serialized.as_bytes()).unwrap();
+}
+
+#[cfg(feature = "serde")]
+pub fn load_json(file_name: &str) -> Result<NeuralNetwork, ModelError> {
+    let network = read(file_name)?;
+    serde_json::from_slice(&network).map_err(ModelError::Json)
+}
+
+pub fn save_safetensors(neural_network: &NeuralNetwork, name: &str) {
+    write_atomic(&format!("networks/{name}"), &to_safetensors(neural_network)).unwrap();
+}
+
+pub fn load_safetensors(file_name: &str) -> Result<NeuralNetwork, ModelError> {
+    from_safetensors(&read(file_name)?)
+}
+
+pub fn save_npz(neural_network: &NeuralNetwork, name: &str) {
+    write_atomic(&format!("networks/{name}"), &to_npz(neural_network)).unwrap();
+}
+
+pub fn load_npz(file_name: &str) -> Result<NeuralNetwork, ModelError> {
+    from_npz(&read(file_name)?)
+}
+
+// the file is written next to the target and then renamed, so an interrupted save never leaves a half-written network
+fn write_atomic(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    write(&tmp, contents)?;
+    fs::rename(&tmp, path)
 }
 
 pub fn load(file_name: &str) -> NeuralNetwork {
-    let network = read_to_string(file_name).unwrap();
-    NeuralNetwork::deserialize(&network)
+    try_load(file_name).unwrap()
+}
+
+// both binary and text networks can be loaded, binary files start with the magic number
+pub fn try_load(file_name: &str) -> Result<NeuralNetwork, ModelError> {
+    parse(&read(file_name)?, false)
+}
+
+// like try_load, but text networks must have the "layer N" sections serialize writes and nothing may follow
+// the last value, use it for networks that are served
+pub fn try_load_strict(file_name: &str) -> Result<NeuralNetwork, ModelError> {
+    parse(&read(file_name)?, true)
+}
+
+// binary networks only, the values stay in the file, see MappedNetwork
+pub fn load_mapped(file_name: &str) -> Result<MappedNetwork, ModelError> {
+    MappedNetwork::open(file_name)
+}
+
+// reads only the beginning of the file, the weights are not loaded
+pub fn read_metadata(file_name: &str) -> Result<Metadata, ModelError> {
+    let file = File::open(file_name)?;
+    let file_size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+        let mut header = vec![0; 16];
+        reader.read_exact(&mut header)?;
+        let header_size = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        // a corrupted header size must not allocate more than the file has
+        if header_size as u64 > file_size {
+            return Err(ModelError::MissingHeader);
+        }
+        header.resize(header_size.max(16), 0);
+        reader.read_exact(&mut header[16..])?;
+        return NeuralNetwork::deserialize_binary_metadata(&header);
+    }
+    let mut text = String::new();
+    for line in reader.lines() {
+        let line = line.map_err(|_| ModelError::InvalidText)?;
+        if NeuralNetwork::is_header(&line) {
+            break;
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    NeuralNetwork::deserialize_metadata(&text)
+}
+
+fn parse(network: &[u8], strict: bool) -> Result<NeuralNetwork, ModelError> {
+    if network.starts_with(BINARY_MAGIC) {
+        return if strict {
+            NeuralNetwork::try_deserialize_binary_strict(network)
+        } else {
+            NeuralNetwork::try_deserialize_binary(network)
+        };
+    }
+    let text = std::str::from_utf8(network).map_err(|_| ModelError::InvalidText)?;
+    if strict {
+        NeuralNetwork::try_deserialize_strict(text)
+    } else {
+        NeuralNetwork::try_deserialize(text)
+    }
 }
 
 pub fn process(input: &[f32], network: &NeuralNetwork) {
@@ -94,3 +500,319 @@ pub fn create(layers: &[u32], name: &str)  {
 
 }
 
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use crate::image::{HEIGHT, WIDTH};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::ops::ControlFlow;
+    use crate::error::ModelError;
+    use crate::dataset::{Dataset, InMemoryDataset};
+    use crate::network_interface::{distill, learn, learn_with_callbacks, learn_with_datasets, read_metadata, resume, try_load, Sampler};
+    use crate::neural_network::NeuralNetwork;
+    use crate::test_util::{temp_dataset, temp_file, temp_path};
+    use crate::training::{EarlyStopping, EpochSummary, Sampling, Schedule, StopCondition, TrainingCallback, TrainingConfig, TrainingProgress, TrainingSummary};
+
+    #[test]
+    fn test_learn_invalid_config() {
+        let mut network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+        let config = TrainingConfig { stop: StopCondition::Iterations(1), log_interval: 0, ..Default::default() };
+        let dataset = InMemoryDataset::from_samples(&[vec![0.0; WIDTH * HEIGHT]], &[3]);
+        let early_stopping = TrainingConfig { early_stopping: Some(EarlyStopping::default()), ..config.clone() };
+        let missing = TrainingConfig { training_dataset: "no_such_dataset".to_string(), ..config.clone() };
+
+        assert!(matches!(learn_with_datasets(&mut network, &config, &InMemoryDataset::from_samples(&[], &[]), None, &mut []),
+            Err(ModelError::InvalidConfig(message)) if message == "the training dataset is empty"));
+        assert!(matches!(learn_with_datasets(&mut network, &early_stopping, &dataset, None, &mut []),
+            Err(ModelError::InvalidConfig(message)) if message == "early stopping needs a validation dataset"));
+        assert!(matches!(learn(&mut network, &missing), Err(ModelError::Io(_))));
+    }
+
+    #[test]
+    fn test_distill_moves_student_to_teacher() {
+        let dataset = temp_dataset("distill", 2);
+        let config = TrainingConfig {
+            learning_rate: 1.0,
+            stop: StopCondition::Iterations(200),
+            batch_size: 4,
+            training_dataset: dataset.clone(),
+            log_interval: 0,
+            seed: Some(3),
+            ..Default::default()
+        };
+        let teacher = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 20, 10]);
+        let mut student = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+        let samples = InMemoryDataset::load(&dataset);
+        let inputs: Vec<&[f32]> = (0..samples.len()).map(|i| samples.get(i).0).collect();
+        let distance = |student: &NeuralNetwork| -> f32 {
+            inputs.iter()
+                .flat_map(|input| student.soft_targets(input, 2.0).into_iter().zip(teacher.soft_targets(input, 2.0)))
+                .map(|(a, b)| (a - b).powi(2))
+                .sum()
+        };
+
+        let before = distance(&student);
+        distill(&mut student, &teacher, 2.0, 1.0, &config).unwrap();
+        let after = distance(&student);
+        fs::remove_dir_all(dataset).unwrap();
+
+        assert!(after < before / 2.0, "{before} {after}");
+    }
+
+    #[test]
+    fn test_learn_with_seed() {
+        let dataset = temp_dataset("learn_with_seed", 2);
+        let config = TrainingConfig {
+            stop: StopCondition::Iterations(20),
+            batch_size: 3,
+            training_dataset: dataset.clone(),
+            validation_dataset: Some(dataset.clone()),
+            log_interval: 0,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let mut first = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+        let mut second = first.clone();
+        let mut epochs = first.clone();
+
+        let first_summary = learn(&mut first, &config).unwrap();
+        let second_summary = learn(&mut second, &config).unwrap();
+        let epochs_summary = learn(&mut epochs, &TrainingConfig { stop: StopCondition::Epochs(2), ..config.clone() }).unwrap();
+        fs::remove_dir_all(dataset).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first_summary.loss, second_summary.loss);
+        assert_eq!((first_summary.iterations, first_summary.samples, first_summary.training_samples), (20, 60, 20));
+        assert_eq!(first_summary.validation_samples, Some(20));
+        assert_eq!(first_summary.metadata().iterations, Some(20));
+        assert_eq!((epochs_summary.iterations, epochs_summary.samples), (14, 42));
+    }
+
+    #[test]
+    fn test_read_metadata_header_size_larger_than_file() {
+        let mut network = NeuralNetwork::new(&[2, 1]).serialize_binary();
+        network[8..12].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        let path = temp_file("read_metadata_header_size", &network);
+
+        let result = read_metadata(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(ModelError::MissingHeader)));
+    }
+
+    #[test]
+    fn test_shuffled_sampler_visits_every_sample_once_per_epoch() {
+        let labels: Vec<u8> = (0..10).flat_map(|digit| vec![digit; digit as usize + 1]).collect();
+        let mut sampler = Sampler::new(&labels, Sampling::Shuffled);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut epochs: Vec<Vec<usize>> = Vec::new();
+        for _ in 0..2 {
+            let mut epoch: Vec<usize> = Vec::new();
+            while epoch.len() < labels.len() {
+                epoch.extend(sampler.next_batch(&mut rng, 4));
+            }
+            epochs.push(epoch);
+        }
+        let mut sorted = epochs[0].clone();
+        sorted.sort();
+
+        assert_eq!(labels.len(), 55);
+        assert_eq!(sorted, (0..55).collect::<Vec<usize>>());
+        assert_ne!(epochs[0], epochs[1]);
+    }
+
+    #[test]
+    fn test_random_sampler_picks_every_digit() {
+        let labels: Vec<u8> = (0..10).flat_map(|digit| vec![digit; 100 * (digit as usize + 1)]).collect();
+        let mut sampler = Sampler::new(&labels, Sampling::Random);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut counts = [0; 10];
+        for i in sampler.next_batch(&mut rng, 1000) {
+            counts[labels[i] as usize] += 1;
+        }
+
+        // every digit about equally often, even though there are 10 times more nines than zeros
+        assert!(counts.iter().all(|&count| (50..150).contains(&count)));
+    }
+
+    #[test]
+    fn test_learn_shuffled_epochs() {
+        let dataset = temp_dataset("learn_shuffled_epochs", 2);
+        let config = TrainingConfig {
+            stop: StopCondition::Epochs(3),
+            batch_size: 3,
+            sampling: Sampling::Shuffled,
+            training_dataset: dataset.clone(),
+            log_interval: 0,
+            ..Default::default()
+        };
+        let mut network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+
+        let summary = learn(&mut network, &config).unwrap();
+        fs::remove_dir_all(dataset).unwrap();
+
+        // 7 batches per epoch, the last one with 2 samples
+        assert_eq!((summary.iterations, summary.samples), (21, 60));
+        assert_eq!(summary.epochs.iter().map(|x| (x.epoch, x.samples)).collect::<Vec<_>>(), vec![(1, 20), (2, 20), (3, 20)]);
+        assert!(summary.epochs.iter().all(|x| x.loss > 0.0 && (0.0..=1.0).contains(&x.accuracy)));
+    }
+
+    #[test]
+    fn test_early_stopping_patience() {
+        let dataset = temp_dataset("early_stopping_patience", 1);
+        // nothing is learned, so only the first evaluation is an improvement
+        let config = TrainingConfig {
+            learning_rate: 0.0,
+            stop: StopCondition::Iterations(100),
+            training_dataset: dataset.clone(),
+            validation_dataset: Some(dataset.clone()),
+            early_stopping: Some(EarlyStopping { interval: 5, patience: 2, ..Default::default() }),
+            log_interval: 0,
+            seed: Some(3),
+            ..Default::default()
+        };
+        let mut network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+
+        let summary = learn(&mut network, &config).unwrap();
+        fs::remove_dir_all(dataset).unwrap();
+
+        assert_eq!(summary.iterations, 15);
+        assert_eq!(summary.best_iteration, Some(5));
+        assert!(summary.stopped_early);
+        assert_eq!(summary.best_accuracy, summary.validation_accuracy);
+    }
+
+    #[test]
+    fn test_early_stopping_restores_best() {
+        let dataset = temp_dataset("early_stopping_restores_best", 2);
+        let path = temp_path("early_stopping_best");
+        let config = TrainingConfig {
+            learning_rate: 5.0,
+            stop: StopCondition::Iterations(40),
+            training_dataset: dataset.clone(),
+            validation_dataset: Some(dataset.clone()),
+            early_stopping: Some(EarlyStopping { interval: 3, patience: 100, save_best: Some(path.clone()), ..Default::default() }),
+            log_interval: 0,
+            seed: Some(5),
+            ..Default::default()
+        };
+        let mut network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+
+        let summary = learn(&mut network, &config).unwrap();
+        let saved = try_load(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        fs::remove_dir_all(dataset).unwrap();
+
+        assert_eq!(summary.iterations, 40);
+        assert!(!summary.stopped_early);
+        assert!(summary.best_iteration.is_some());
+        // the restored network is the saved one and has the best accuracy
+        assert_eq!(network, saved);
+        assert_eq!(summary.validation_accuracy, summary.best_accuracy);
+    }
+
+    #[test]
+    fn test_resume_continues_exactly() {
+        let dataset = temp_dataset("resume_continues_exactly", 2);
+        let path = temp_path("resume_checkpoint");
+        // every part of the state is used: shuffled order, stochastic rates, early stopping, unfinished epochs and logs
+        let config = TrainingConfig {
+            stop: StopCondition::Iterations(40),
+            schedule: Schedule::Stochastic { factors: vec![(1.0, 0.7), (4.0, 0.3)] },
+            batch_size: 3,
+            sampling: Sampling::Shuffled,
+            training_dataset: dataset.clone(),
+            validation_dataset: Some(dataset.clone()),
+            early_stopping: Some(EarlyStopping { interval: 7, patience: 100, ..Default::default() }),
+            log_interval: 6,
+            seed: Some(11),
+            checkpoint: Some(path.clone()),
+            checkpoint_interval: 10,
+            ..Default::default()
+        };
+        let network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+
+        let mut uninterrupted = network.clone();
+        let expected = learn(&mut uninterrupted, &config).unwrap();
+        let mut interrupted = network.clone();
+        learn(&mut interrupted, &TrainingConfig { stop: StopCondition::Iterations(25), ..config.clone() }).unwrap();
+        // the checkpoint of iteration 20, the 5 steps after it are lost
+        let mut resumed = NeuralNetwork::empty();
+        let actual = resume(&mut resumed, &config, &path).unwrap();
+        fs::remove_file(path).unwrap();
+        fs::remove_dir_all(dataset).unwrap();
+
+        assert_eq!(resumed, uninterrupted);
+        assert_eq!((actual.iterations, actual.samples, actual.seed), (40, 115, 11));
+        assert_eq!(actual.loss, expected.loss);
+        assert_eq!(actual.best_iteration, expected.best_iteration);
+        assert_eq!(actual.validation_accuracy, expected.validation_accuracy);
+        let epochs = |summary: &TrainingSummary| summary.epochs.iter().map(|x| (x.epoch, x.samples, x.loss, x.accuracy)).collect::<Vec<_>>();
+        assert_eq!(epochs(&actual), epochs(&expected));
+    }
+
+    // records the hooks and stops after max_steps
+    #[derive(Default)]
+    struct Recorder {
+        max_steps: u64,
+        steps: Vec<(u64, f32)>,
+        intervals: Vec<u64>,
+        epochs: Vec<(u64, u64)>,
+        end: Option<(u64, u64, bool)>,
+    }
+
+    impl TrainingCallback for Recorder {
+        fn on_step_end(&mut self, progress: &TrainingProgress) -> ControlFlow<()> {
+            self.steps.push((progress.iteration, progress.learning_rate));
+            if progress.iteration >= self.max_steps { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        }
+
+        fn on_interval(&mut self, progress: &TrainingProgress) -> ControlFlow<()> {
+            self.intervals.push(progress.iteration);
+            ControlFlow::Continue(())
+        }
+
+        fn on_epoch_end(&mut self, progress: &TrainingProgress, epoch: &EpochSummary) -> ControlFlow<()> {
+            self.epochs.push((progress.epoch, epoch.samples));
+            ControlFlow::Continue(())
+        }
+
+        fn on_training_end(&mut self, progress: &TrainingProgress, summary: &TrainingSummary) {
+            self.end = Some((progress.iteration, summary.iterations, summary.stopped_early));
+        }
+    }
+
+    #[test]
+    fn test_training_callbacks() {
+        let dataset = temp_dataset("training_callbacks", 1);
+        let config = TrainingConfig {
+            stop: StopCondition::Iterations(100),
+            schedule: Schedule::Exponential { decay: 0.5 },
+            batch_size: 2,
+            sampling: Sampling::Shuffled,
+            training_dataset: dataset.clone(),
+            log_interval: 4,
+            seed: Some(2),
+            ..Default::default()
+        };
+        let mut network = NeuralNetwork::new(&[(WIDTH * HEIGHT) as u32, 5, 10]);
+        let mut recorder = Recorder { max_steps: 12, ..Default::default() };
+        let mut never_stops = Recorder { max_steps: u64::MAX, ..Default::default() };
+
+        let summary = learn_with_callbacks(&mut network, &config, &mut [&mut recorder, &mut never_stops]).unwrap();
+        fs::remove_dir_all(dataset).unwrap();
+
+        assert_eq!(summary.iterations, 12);
+        assert!(summary.stopped_early);
+        assert_eq!(recorder.steps.iter().map(|x| x.0).collect::<Vec<u64>>(), (1..=12).collect::<Vec<u64>>());
+        assert_eq!(recorder.steps[..3], [(1, 0.5), (2, 0.25), (3, 0.125)]);
+        assert_eq!(recorder.intervals, vec![4, 8, 12]);
+        // 10 samples, 5 steps per epoch
+        assert_eq!(recorder.epochs, vec![(1, 10), (2, 10)]);
+        assert_eq!(recorder.end, Some((12, 12, true)));
+        assert_eq!(never_stops.steps.len(), 12);
+    }
+}
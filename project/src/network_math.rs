@@ -15,3 +15,18 @@ pub fn sum(vec1_result: &mut Vec<f32>, vec2: &Vec<f32>) {
         vec1_result[i] = vec1_result[i] + vec2[i];
     }
 }
+
+pub fn argmax(vec: &[f32]) -> usize {
+    let mut best = 0;
+    for (i, &x) in vec.iter().enumerate() {
+        if x > vec[best] {
+            best = i;
+        }
+    }
+    best
+}
+
+// the loss training_step minimizes, its derivative is output - target
+pub fn squared_error(output: &[f32], target: &[f32]) -> f32 {
+    output.iter().zip(target).map(|(a, y)| 0.5 * (a - y) * (a - y)).sum()
+}
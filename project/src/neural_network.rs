@@ -1,14 +1,49 @@
 use rand::random;
+use crate::checksum;
+use crate::error::ModelError;
+use crate::metadata::Metadata;
 use crate::network_math;
 
+// binary format, all numbers are little-endian:
+// magic, version u16, flags u16, header size u32, number of layers u32, layer sizes u32...,
+// zero padding up to the header size, then f32 values in the same order as in the text format;
+// the header size lets newer versions add fields before the values without breaking older files
+pub const BINARY_MAGIC: &[u8; 4] = b"NNET";
+pub const BINARY_VERSION: u16 = 1;
+// the values are followed by crc32 of their bytes
+pub const BINARY_CHECKSUM: u16 = 1;
+// the layer sizes are followed by metadata: length u32, "key value" lines, zero padding to 4 bytes
+pub const BINARY_METADATA: u16 = 2;
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Parameters"))]
 pub struct NeuralNetwork {
     pub weights: Vec<Vec<Vec<f32>>>,
     pub biases: Vec<Vec<f32>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pre_activations: Vec<Vec<f32>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     activations: Vec<Vec<f32>>,
 }
 
+// deserialized networks are checked with try_from_parameters
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct Parameters {
+    weights: Vec<Vec<Vec<f32>>>,
+    biases: Vec<Vec<f32>>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<Parameters> for NeuralNetwork {
+    type Error = ModelError;
+
+    fn try_from(parameters: Parameters) -> Result<Self, Self::Error> {
+        NeuralNetwork::try_from_parameters(parameters.weights, parameters.biases)
+    }
+}
+
 impl PartialEq for NeuralNetwork {
     fn eq(&self, other: &Self) -> bool {
         self.weights == other.weights && self.biases == other.biases
@@ -16,7 +51,7 @@ impl PartialEq for NeuralNetwork {
 }
 
 impl NeuralNetwork {
-    fn activation(x: f32) -> f32 {
+    pub(crate) fn activation(x: f32) -> f32 {
         1.0 / (1.0 + (-x).exp())
     }
 
@@ -45,11 +80,16 @@ impl NeuralNetwork {
             }
         }
 
+        NeuralNetwork::from_parameters(weights, biases)
+    }
+
+    // the caller is responsible for consistent sizes, weights[layer][neuron] has one value per neuron of the previous layer
+    pub fn from_parameters(weights: Vec<Vec<Vec<f32>>>, biases: Vec<Vec<f32>>) -> Self {
         let mut pre_activations: Vec<Vec<f32>> = Vec::new();
         let mut activations: Vec<Vec<f32>> = Vec::new();
 
-        for i in 0..biases.len() {
-            let empty_vec = vec![0.0; biases[i].len()];
+        for b in &biases {
+            let empty_vec = vec![0.0; b.len()];
             pre_activations.push(empty_vec.clone());
             activations.push(empty_vec);
         }
@@ -59,10 +99,48 @@ impl NeuralNetwork {
         }
     }
 
+    // the same as from_parameters, but the sizes are checked
+    pub fn try_from_parameters(weights: Vec<Vec<Vec<f32>>>, biases: Vec<Vec<f32>>) -> Result<Self, ModelError> {
+        if weights.is_empty() || weights[0].is_empty() {
+            return Err(ModelError::MissingHeader);
+        }
+        if weights.len() != biases.len() {
+            return Err(ModelError::ShapeMismatch { name: "layers".to_string(), expected: vec![weights.len()], actual: vec![biases.len()] });
+        }
+        let mut inputs = weights[0][0].len();
+        if inputs == 0 {
+            return Err(ModelError::BadLayerSize { layer: 0, value: "0".to_string() });
+        }
+        for layer in 0..weights.len() {
+            let neurons = biases[layer].len();
+            if neurons == 0 {
+                return Err(ModelError::BadLayerSize { layer: layer + 1, value: "0".to_string() });
+            }
+            let wrong_row = weights[layer].iter().find(|row| row.len() != inputs);
+            if weights[layer].len() != neurons || wrong_row.is_some() {
+                let actual = vec![weights[layer].len(), wrong_row.or(weights[layer].first()).map_or(0, |row| row.len())];
+                let name = format!("layer {} weights", layer + 1);
+                return Err(ModelError::ShapeMismatch { name, expected: vec![neurons, inputs], actual });
+            }
+            inputs = neurons;
+        }
+        Ok(NeuralNetwork::from_parameters(weights, biases))
+    }
+
     pub fn empty() -> Self {
         Self { weights: vec![], biases: vec![], pre_activations: vec![], activations: vec![] }
     }
 
+    // sizes of all layers including the input, for example [784, 800, 10]
+    pub fn layers(&self) -> Vec<u32> {
+        let mut result: Vec<u32> = Vec::new();
+        result.push(self.weights[0][0].len() as u32);
+        for b in &self.biases {
+            result.push(b.len() as u32);
+        }
+        result
+    }
+
     pub fn process(&self, input: &[f32]) -> Vec<f32> {
         //todo improve it, parallelize and calculate product in sum in a one go instead of separate functions, limit allocations
         let mut prev = input.to_vec();
@@ -79,6 +157,67 @@ impl NeuralNetwork {
         result
     }
 
+    // activations of every layer except the input, the last one is the output
+    pub fn layer_activations(&self, input: &[f32]) -> Vec<Vec<f32>> {
+        let mut result: Vec<Vec<f32>> = Vec::new();
+        for i in 0..self.weights.len() {
+            let prev = if i == 0 { input } else { &result[i - 1] };
+            let mut layer = vec![0.0; self.weights[i].len()];
+            network_math::product(&self.weights[i], prev, &mut layer);
+            network_math::sum(&mut layer, &self.biases[i]);
+            layer.iter_mut().for_each(|x| *x = NeuralNetwork::activation(*x));
+            result.push(layer);
+        }
+        result
+    }
+
+    // gradient of the chosen output with respect to every input value
+    pub fn input_gradient(&self, input: &[f32], output: usize) -> Vec<f32> {
+        let activations = self.layer_activations(input);
+        let last = activations.len() - 1;
+        let mut deltas = vec![0.0; activations[last].len()];
+        let a = activations[last][output];
+        deltas[output] = a * (1.0 - a);
+        for layer in (0..self.weights.len()).rev() {
+            let mut prev_deltas = vec![0.0; self.weights[layer][0].len()];
+            for (i, delta) in deltas.iter().enumerate() {
+                for (j, prev_delta) in prev_deltas.iter_mut().enumerate() {
+                    *prev_delta += delta * self.weights[layer][i][j];
+                }
+            }
+            if layer > 0 {
+                for (j, prev_delta) in prev_deltas.iter_mut().enumerate() {
+                    let a = activations[layer - 1][j];
+                    *prev_delta *= a * (1.0 - a);
+                }
+            }
+            deltas = prev_deltas;
+        }
+        deltas
+    }
+
+    // the last layer values before the activation function
+    pub fn process_logits(&self, input: &[f32]) -> Vec<f32> {
+        let mut prev = input.to_vec();
+        let mut result = Vec::new();
+        for i in 0..self.weights.len() {
+            result = vec![0.0; self.weights[i].len()];
+            network_math::product(&self.weights[i], &prev, &mut result);
+            network_math::sum(&mut result, &self.biases[i]);
+            if i + 1 < self.weights.len() {
+                result.iter_mut().for_each(|x| *x = NeuralNetwork::activation(*x));
+                prev = result.clone();
+            }
+        }
+        result
+    }
+
+    // outputs softened by the temperature, the higher the temperature the closer the outputs are to 0.5,
+    // temperature 1.0 gives the same result as process
+    pub fn soft_targets(&self, input: &[f32], temperature: f32) -> Vec<f32> {
+        self.process_logits(input).iter().map(|&x| NeuralNetwork::activation(x / temperature)).collect()
+    }
+
     pub fn process_mutable(&mut self, input: &[f32]) -> Vec<f32> {
         //todo improve it, parallelize and calculate product in sum in a one go instead of separate functions
         for i in 0..self.weights.len() {
@@ -92,18 +231,57 @@ impl NeuralNetwork {
         self.activations.last().unwrap().clone()
     }
 
-    pub fn training_step(&mut self, inputs: &[f32], targets: &[f32], learning_rate: f32) {
-        let processed = self.process_mutable(inputs);
+    // one step of gradient descent on a single sample, returns the loss before the step
+    pub fn training_step(&mut self, inputs: &[f32], targets: &[f32], learning_rate: f32) -> f32 {
+        let outputs = self.training_batch(&[(inputs, targets)], learning_rate);
+        network_math::squared_error(&outputs[0], targets)
+    }
+
+    // one step with the gradients averaged over the batch, returns the outputs of every sample before the step,
+    // an empty batch has no gradients, so it's no step
+    pub fn training_batch(&mut self, batch: &[(&[f32], &[f32])], learning_rate: f32) -> Vec<Vec<f32>> {
+        self.training_batch_with(batch, learning_rate, |i, _, outputs| {
+            outputs.iter().zip(batch[i].1.iter()).map(|(&a, &y)| (a - y) * a * (1.0 - a)).collect()
+        })
+    }
+
+    // the same as training_batch with another loss, output_deltas gets the index of the sample in the batch,
+    // the last layer values before the activation and the outputs, and returns the derivatives of the loss
+    // with respect to the values before the activation
+    pub(crate) fn training_batch_with(&mut self, batch: &[(&[f32], &[f32])], learning_rate: f32, output_deltas: impl Fn(usize, &[f32], &[f32]) -> Vec<f32>) -> Vec<Vec<f32>> {
+        if batch.is_empty() {
+            return Vec::new();
+        }
+        let mut gradients_weights: Vec<Vec<Vec<f32>>> = self.weights.iter().map(|x| x.iter().map(|y| vec![0.0; y.len()]).collect()).collect();
+        let mut gradients_biases: Vec<Vec<f32>> = self.biases.iter().map(|x| vec![0.0; x.len()]).collect();
+        let mut outputs = Vec::new();
+        for (i, (inputs, _)) in batch.iter().enumerate() {
+            outputs.push(self.backpropagate(inputs, |logits, outputs| output_deltas(i, logits, outputs), &mut gradients_biases, &mut gradients_weights));
+        }
+        let learning_rate = learning_rate / batch.len() as f32;
+
+        // update weights and biases
+        for layer in 0..gradients_biases.len() {
+            for i in 0..gradients_biases[layer].len() {
+                for j in 0..gradients_weights[layer][i].len() {
+                    self.weights[layer][i][j] -= learning_rate * gradients_weights[layer][i][j];
+                }
+                self.biases[layer][i] -= learning_rate * gradients_biases[layer][i];
+            }
+        }
+        outputs
+    }
 
+    // adds the gradients of the sample to the gradients, returns the output
+    fn backpropagate(&mut self, inputs: &[f32], output_deltas: impl Fn(&[f32], &[f32]) -> Vec<f32>, gradients_biases: &mut Vec<Vec<f32>>, gradients_weights: &mut Vec<Vec<Vec<f32>>>) -> Vec<f32> {
+        let processed = self.process_mutable(inputs);
         let mut deltas: Vec<Vec<f32>> = self.biases.iter().map(|x| x.iter().map(|_| 0.0).collect()).collect();
-        let mut gradients_weights = self.weights.clone();
-        let mut gradients_biases = self.biases.clone();
 
         // calculate last layer gradients
         let layer = self.weights.len() - 1;
-        deltas[layer] = processed.iter().zip(targets.iter()).map(|(&a, &y)| (a - y) * a * (1.0 - a)).collect();
+        deltas[layer] = output_deltas(&self.pre_activations[layer], &processed);
         // deltas[layer] = processed.iter().zip(targets.iter()).map(|(&a, &y)| (y - a) * a * (1.0 - a)).collect();
-        Self::update_gradients(layer, &deltas, inputs, &mut gradients_biases, &mut gradients_weights, &self.activations);
+        Self::update_gradients(layer, &deltas, inputs, gradients_biases, gradients_weights, &self.activations);
         // for i in 0..deltas[layer].len() {
         //     gradients_biases[layer][i] = deltas[layer][i];
         //     for j in 0..gradients_weights[layer][i].len() {
@@ -131,7 +309,7 @@ impl NeuralNetwork {
                 // }
             }
 
-            Self::update_gradients(layer, &deltas, inputs, &mut gradients_biases, &mut gradients_weights, &self.activations);
+            Self::update_gradients(layer, &deltas, inputs, gradients_biases, gradients_weights, &self.activations);
         }
 
         // println!("weights {:?}", &self.weights[0][0][0..5]);
@@ -140,35 +318,15 @@ impl NeuralNetwork {
         // println!("deltas {} {:?}", deltas[1].iter().all(|&x| x == 0.0), &deltas[0][0..10]);
         // println!("deltas {:?}", deltas);
 
-        // update weights and biases
-        for layer in 0..gradients_biases.len() {
-            for i in 0..gradients_biases[layer].len() {
-                for j in 0..gradients_weights[layer][i].len() {
-                    let prev_val = self.weights[layer][i][j];
-                    self.weights[layer][i][j] -= learning_rate * gradients_weights[layer][i][j];
-                    // if prev_val == self.weights[layer][i][j] {
-                    //     println!("layer {}; i {}; j {}; weight {}", layer, i, j, gradients_weights[layer][i][j]);
-                    //     panic!();
-                    // }
-                }
-                self.biases[layer][i] -= learning_rate * gradients_biases[layer][i];
-                // if self.biases[layer][i] == prev_val {
-                //     println!("{deltas:?}");
-                //     println!("{prev_val} {}", self.biases[layer][i]);
-                //     println!("{}", gradients_biases == deltas);
-                //     println!("layer {layer}; i {i}; gradient {}; {}", gradients_biases[layer][i], learning_rate * gradients_biases[layer][i]);
-                //     panic!();
-                // }
-            }
-        }
+        processed
     }
 
     fn update_gradients(layer: usize, deltas: &Vec<Vec<f32>>, inputs: &[f32], gradients_biases: &mut Vec<Vec<f32>>, gradients_weights: &mut Vec<Vec<Vec<f32>>>, activations: &Vec<Vec<f32>>) {
         for i in 0..deltas[layer].len() {
-            gradients_biases[layer][i] = deltas[layer][i];
+            gradients_biases[layer][i] += deltas[layer][i];
             for j in 0..gradients_weights[layer][i].len() {
                 let prev = if layer == 0 { inputs } else { &activations[layer-1] };
-                gradients_weights[layer][i][j] = deltas[layer][i] * prev[j];
+                gradients_weights[layer][i][j] += deltas[layer][i] * prev[j];
                 // if gradients_weights[layer][i][j] == 0.0 {
                 //     println!("layer {}; i {}; j {}; gradient {}; prev {:?}", layer, i, j, gradients_weights[layer][i][j], prev[j]);
                 //     panic!();
@@ -177,69 +335,320 @@ impl NeuralNetwork {
         }
     }
 
-    pub fn serialize(&self) -> String {
-        // todo serialize to binary instead of string
-        let mut header: Vec<u32> = Vec::new();
-        header.push(self.weights[0][0].len() as u32);
-        for b in &self.biases {
-            header.push(b.len() as u32);
+    // all weights and biases in the order they are saved: layer by layer, for every neuron its weights and then its bias
+    pub fn parameters(&self) -> Vec<f32> {
+        let mut result = Vec::new();
+        for layer in 0..self.weights.len() {
+            for neuron in 0..self.biases[layer].len() {
+                result.extend_from_slice(&self.weights[layer][neuron]);
+                result.push(self.biases[layer][neuron]);
+            }
         }
+        result
+    }
+
+    pub fn serialize_with_metadata(&self, metadata: &Metadata) -> String {
+        let mut lines = metadata.to_lines();
+        lines.push(self.serialize());
+        lines.join("\n")
+    }
+
+    pub fn serialize(&self) -> String {
+        let header = self.layers();
+        let parameters = self.parameters();
 
         let mut result: Vec<String> = Vec::new();
+        let mut values = parameters.iter();
         for layer in 1..header.len() {
             result.push(format!("layer {}", layer));
-            for current_layer_i in 0..header[layer] as usize {
-                for prev_layer_i in 0..header[layer - 1] as usize {
-                    result.push(self.weights[layer - 1][current_layer_i][prev_layer_i].to_string());
-                }
-                result.push(self.biases[layer - 1][current_layer_i].to_string());
+            for _ in 0..(header[layer - 1] as usize + 1) * header[layer] as usize {
+                result.push(values.next().unwrap().to_string());
             }
         }
+        result.push(format!("checksum {:08x}", checksum::crc32_values(&parameters)));
 
         let header = header.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" ");
         let body = result.join("\n");
         header + "\n" + &body
     }
 
+    pub fn serialize_binary(&self) -> Vec<u8> {
+        self.serialize_binary_with_metadata(&Metadata::default())
+    }
+
+    pub fn serialize_binary_with_metadata(&self, metadata: &Metadata) -> Vec<u8> {
+        let layers = self.layers();
+        let parameters = self.parameters();
+        let mut flags = BINARY_CHECKSUM;
+        let mut metadata_block: Vec<u8> = Vec::new();
+        if !metadata.is_empty() {
+            flags |= BINARY_METADATA;
+            let text = metadata.to_lines().join("\n");
+            metadata_block.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            metadata_block.extend_from_slice(text.as_bytes());
+            metadata_block.resize(metadata_block.len().next_multiple_of(4), 0);
+        }
+        let header_size = 16 + 4 * layers.len() as u32 + metadata_block.len() as u32;
+        let mut result: Vec<u8> = Vec::new();
+        result.extend_from_slice(BINARY_MAGIC);
+        result.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+        result.extend_from_slice(&flags.to_le_bytes());
+        result.extend_from_slice(&header_size.to_le_bytes());
+        result.extend_from_slice(&(layers.len() as u32).to_le_bytes());
+        for size in &layers {
+            result.extend_from_slice(&size.to_le_bytes());
+        }
+        result.extend_from_slice(&metadata_block);
+        for value in &parameters {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+        result.extend_from_slice(&checksum::crc32_values(&parameters).to_le_bytes());
+        result
+    }
+
+    pub fn deserialize_binary(input: &[u8]) -> Self {
+        Self::try_deserialize_binary(input).unwrap()
+    }
+
+    pub fn try_deserialize_binary(input: &[u8]) -> Result<Self, ModelError> {
+        Self::parse_binary(input, false)
+    }
+
+    // the same as try_deserialize_binary, but any bytes after the values and the checksum are an error
+    pub fn try_deserialize_binary_strict(input: &[u8]) -> Result<Self, ModelError> {
+        Self::parse_binary(input, true)
+    }
+
+    fn parse_binary(input: &[u8], strict: bool) -> Result<Self, ModelError> {
+        let (header, header_size, checksum) = Self::parse_binary_header(input)?;
+        let mut end = header_size + 4 * Self::parameter_count(&header)?;
+        let values: Vec<f32> = input[header_size..end].chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect();
+
+        if let Some(expected) = checksum {
+            Self::check_checksum(expected, &values)?;
+            end += 4;
+        }
+        if strict && input.len() > end {
+            return Err(ModelError::TrailingBytes { count: input.len() - end });
+        }
+
+        Ok(Self::from_values(&header, &values))
+    }
+
+    // returns the layer sizes, the header size (where the values start) and the stored checksum,
+    // the input is checked to contain all values and the checksum
+    pub(crate) fn parse_binary_header(input: &[u8]) -> Result<(Vec<u32>, usize, Option<u32>), ModelError> {
+        let u32_at = |offset: usize| u32::from_le_bytes(input[offset..offset + 4].try_into().unwrap());
+        if input.len() < 16 || &input[0..4] != BINARY_MAGIC {
+            return Err(ModelError::MissingHeader);
+        }
+        let version = u16::from_le_bytes(input[4..6].try_into().unwrap());
+        if version > BINARY_VERSION {
+            return Err(ModelError::UnsupportedVersion(version));
+        }
+        let flags = u16::from_le_bytes(input[6..8].try_into().unwrap());
+        let header_size = u32_at(8) as usize;
+        let layer_count = u32_at(12) as usize;
+        if header_size < 16 + 4 * layer_count || header_size > input.len() {
+            return Err(ModelError::MissingHeader);
+        }
+        let header: Vec<u32> = (0..layer_count).map(|i| u32_at(16 + 4 * i)).collect();
+        Self::check_layers(&header)?;
+
+        // check the size first, so a corrupted header doesn't allocate a huge network
+        let expected = Self::parameter_count(&header)?;
+        let available = (input.len() - header_size) / 4;
+        if available < expected {
+            return Err(ModelError::UnexpectedEof { expected, actual: available });
+        }
+        let end = header_size + 4 * expected;
+        if flags & BINARY_CHECKSUM == 0 {
+            return Ok((header, header_size, None));
+        }
+        if input.len() < end + 4 {
+            return Err(ModelError::UnexpectedEof { expected: expected + 1, actual: expected });
+        }
+        Ok((header, header_size, Some(u32_at(end))))
+    }
+
+    // only the header is read, it's enough to pass the beginning of the file up to the header size
+    pub fn deserialize_binary_metadata(input: &[u8]) -> Result<Metadata, ModelError> {
+        if input.len() < 16 || &input[0..4] != BINARY_MAGIC {
+            return Err(ModelError::MissingHeader);
+        }
+        let flags = u16::from_le_bytes(input[6..8].try_into().unwrap());
+        if flags & BINARY_METADATA == 0 {
+            return Ok(Metadata::default());
+        }
+        let u32_at = |offset: usize| input.get(offset..offset + 4).map(|x| u32::from_le_bytes(x.try_into().unwrap()));
+        let start = 16 + 4 * u32_at(12).unwrap() as usize;
+        let length = u32_at(start).ok_or(ModelError::MissingHeader)? as usize;
+        let text = input.get(start + 4..start + 4 + length).ok_or(ModelError::MissingHeader)?;
+        let text = std::str::from_utf8(text).map_err(|_| ModelError::InvalidText)?;
+        Metadata::parse(text.lines(), 1)
+    }
+
+    // metadata is everything before the header
+    pub fn deserialize_metadata(input: &str) -> Result<Metadata, ModelError> {
+        Metadata::parse(input.lines().take_while(|&s| !Self::is_header(s)), 1)
+    }
+
     pub fn deserialize(input: &str) -> Self {
-        //todo handle errors, return Err
-        let mut it = input.split("\n");
-        // the first line with only numbers is considered the header, for example "2 3 2 1"
-        let header: Vec<u32> = it.find_map(
-            |s| {
-                let split: Vec<&str> = s.split(' ').collect();
-                if split.len() < 2 {
-                    return None;
-                }
-                let values: Vec<u32> = split.iter().map_while(|&x| x.parse().ok()).collect();
-                if values.len() == split.len() {
-                    Some(values)
-                }
-                else {
-                    None
-                }
-            }
-        ).unwrap();
-        let mut result = NeuralNetwork::new(&header);
+        Self::try_deserialize(input).unwrap()
+    }
+
+    // labels like "layer 1", empty lines and anything after the last value are skipped
+    pub fn try_deserialize(input: &str) -> Result<Self, ModelError> {
+        Self::parse_text(input)
+    }
 
+    // the format serialize writes: every layer starts with a "layer N" line followed by its neurons,
+    // each one its weights and then its bias, the only line allowed after the last layer is the checksum;
+    // metadata before the header and empty lines are allowed, errors name the layer or the line that is wrong
+    pub fn try_deserialize_strict(input: &str) -> Result<Self, ModelError> {
+        let mut lines = input.lines().enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, s)| !s.is_empty())
+            .peekable();
+        let header = Self::parse_header(&mut lines)?;
+        Self::check_layers(&header)?;
+        let expected = Self::parameter_count(&header)?;
+
+        let mut values: Vec<f32> = Vec::new();
         for layer in 1..header.len() {
-            for current_layer_i in 0..header[layer] as usize {
-                for prev_layer_i in 0..header[layer - 1] as usize {
-                    let val: f32 = it.find_map(|x| x.parse().ok()).unwrap();
-                    result.weights[layer - 1][current_layer_i][prev_layer_i] = val;
+            let marker = format!("layer {layer}");
+            let start = match lines.next() {
+                Some((line, s)) if s == marker => line,
+                Some((line, s)) => return Err(ModelError::UnexpectedSection { line, expected: marker, actual: s.to_string() }),
+                None => return Err(ModelError::UnexpectedEof { expected, actual: values.len() }),
+            };
+            let count = values.len();
+            while let Some(&(line, s)) = lines.peek() {
+                if s.starts_with("layer ") || s.starts_with("checksum ") {
+                    break;
                 }
-                let val: f32 = it.find_map(|x| x.parse().ok()).unwrap();
-                result.biases[layer - 1][current_layer_i] = val;
+                values.push(s.parse().map_err(|_| ModelError::UnparsableValue { line, value: s.to_string() })?);
+                lines.next();
+            }
+            let (inputs, neurons) = (header[layer - 1] as usize, header[layer] as usize);
+            if values.len() - count != (inputs + 1) * neurons {
+                return Err(ModelError::SectionSize { layer, line: start, inputs, neurons, actual: values.len() - count });
             }
         }
 
-        result
+        if let Some((line, s)) = lines.next() {
+            let hex = s.strip_prefix("checksum ").ok_or(ModelError::TrailingLine { line, value: s.to_string() })?;
+            let checksum = u32::from_str_radix(hex, 16).map_err(|_| ModelError::UnparsableValue { line, value: s.to_string() })?;
+            Self::check_checksum(checksum, &values)?;
+        }
+        if let Some((line, s)) = lines.next() {
+            return Err(ModelError::TrailingLine { line, value: s.to_string() });
+        }
+
+        Ok(Self::from_values(&header, &values))
+    }
+
+    fn parse_text(input: &str) -> Result<Self, ModelError> {
+        let mut lines = input.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+        let header = Self::parse_header(&mut lines)?;
+        Self::check_layers(&header)?;
+
+        let expected = Self::parameter_count(&header)?;
+        let mut values: Vec<f32> = Vec::new();
+        let mut checksum = None;
+        for (line, s) in lines {
+            if let Some(hex) = s.strip_prefix("checksum ") {
+                let value = u32::from_str_radix(hex, 16).map_err(|_| ModelError::UnparsableValue { line, value: s.to_string() })?;
+                checksum = Some(value);
+            } else if values.len() == expected {
+                continue;
+            } else if let Ok(value) = s.parse::<f32>() {
+                values.push(value);
+            } else if !s.is_empty() && !s.starts_with(char::is_alphabetic) {
+                return Err(ModelError::UnparsableValue { line, value: s.to_string() });
+            }
+        }
+
+        if values.len() < expected {
+            return Err(ModelError::UnexpectedEof { expected, actual: values.len() });
+        }
+        if let Some(checksum) = checksum {
+            Self::check_checksum(checksum, &values)?;
+        }
+
+        Ok(Self::from_values(&header, &values))
+    }
+
+    // the first line with only numbers is considered the header, for example "2 3 2 1"
+    pub(crate) fn is_header(line: &str) -> bool {
+        let split: Vec<&str> = line.split_whitespace().collect();
+        split.len() >= 2 && split.iter().all(|x| x.parse::<f32>().is_ok())
+    }
+
+    fn parse_header<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Result<Vec<u32>, ModelError> {
+        for (_, s) in lines {
+            if !Self::is_header(s) {
+                continue;
+            }
+            return s.split_whitespace()
+                .enumerate()
+                .map(|(layer, value)| value.parse().map_err(|_| ModelError::BadLayerSize { layer, value: value.to_string() }))
+                .collect();
+        }
+        Err(ModelError::MissingHeader)
+    }
+
+    fn check_layers(header: &[u32]) -> Result<(), ModelError> {
+        if header.len() < 2 {
+            return Err(ModelError::MissingHeader);
+        }
+        match header.iter().position(|&size| size == 0) {
+            Some(layer) => Err(ModelError::BadLayerSize { layer, value: "0".to_string() }),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_checksum(expected: u32, values: &[f32]) -> Result<(), ModelError> {
+        let actual = checksum::crc32_values(values);
+        if expected != actual {
+            return Err(ModelError::ChecksumMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
+    // number of weights and biases of a network with the given layer sizes,
+    // sizes read from a file can overflow it, the error names the layer that did
+    pub(crate) fn parameter_count(header: &[u32]) -> Result<usize, ModelError> {
+        header.windows(2).enumerate().try_fold(0usize, |count, (i, x)| {
+            (x[0] as usize).checked_add(1)
+                .and_then(|inputs| inputs.checked_mul(x[1] as usize))
+                .and_then(|parameters| parameters.checked_add(count))
+                .ok_or(ModelError::BadLayerSize { layer: i + 1, value: x[1].to_string() })
+        })
+    }
+
+    // the values have to be in the order of parameters(), there must be exactly parameter_count(header) of them
+    fn from_values(header: &[u32], values: &[f32]) -> Self {
+        let mut values = values.iter().copied();
+        let mut weights: Vec<Vec<Vec<f32>>> = Vec::new();
+        let mut biases: Vec<Vec<f32>> = Vec::new();
+        for layer in 1..header.len() {
+            weights.push(Vec::new());
+            biases.push(Vec::new());
+            for _ in 0..header[layer] {
+                weights[layer - 1].push(values.by_ref().take(header[layer - 1] as usize).collect());
+                biases[layer - 1].push(values.next().unwrap());
+            }
+        }
+        NeuralNetwork::from_parameters(weights, biases)
     }
 }
 
+#[cfg(test)]
 mod test {
-    use std::cmp::PartialEq;
-    use crate::neural_network::NeuralNetwork;
+    use crate::error::ModelError;
+    use crate::metadata::Metadata;
+    use crate::neural_network::{NeuralNetwork, BINARY_MAGIC, BINARY_VERSION};
 
     fn get_network() -> NeuralNetwork {
         NeuralNetwork {
@@ -308,7 +717,10 @@ mod test {
         let network = get_network();
         let result = network.serialize();
         let expected = "2 3 2 1\n\
-        0.1\n0.2\n-0.5\n0.2\n0.3\n0.3\n0.4\n0.5\n0.5\n0.5\n0.6\n0.7\n0.2\n0.5\n0.1\n0.2\n-0.9\n0.3\n0.4\n0.6";
+        layer 1\n0.1\n0.2\n-0.5\n0.2\n0.3\n0.3\n0.4\n0.5\n0.5\n\
+        layer 2\n0.5\n0.6\n0.7\n0.2\n0.5\n0.1\n0.2\n-0.9\n\
+        layer 3\n0.3\n0.4\n0.6\n\
+        checksum b123a352";
 
         assert_eq!(result, expected);
     }
@@ -323,14 +735,83 @@ mod test {
         assert_eq!(deserialized, network);
     }
 
+    #[test]
+    fn test_serialize_binary() {
+        let network = get_network();
+        let result = network.serialize_binary();
+
+        assert_eq!(&result[0..4], b"NNET");
+        assert_eq!(result[4..6], [1, 0]);
+        assert_eq!(result[6..8], [1, 0]);
+        assert_eq!(result[8..12], [32, 0, 0, 0]);
+        assert_eq!(result[12..16], [4, 0, 0, 0]);
+        assert_eq!(result[16..32], [2, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(result[32..36], 0.1_f32.to_le_bytes());
+        assert_eq!(result[112..116], 0xb123a352_u32.to_le_bytes());
+        assert_eq!(result.len(), 32 + 4 * 20 + 4);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_binary() {
+        let network = NeuralNetwork::new(&[5, 7, 10, 10]);
+
+        let serialized = network.serialize_binary();
+        let deserialized = NeuralNetwork::deserialize_binary(&serialized);
+
+        assert_eq!(deserialized, network);
+    }
+
+    #[test]
+    fn test_serialize_with_metadata() {
+        let network = get_network();
+        let metadata = Metadata { iterations: Some(500), learning_rate: Some(0.5), ..Metadata::new() };
+
+        let text = network.serialize_with_metadata(&metadata);
+        let binary = network.serialize_binary_with_metadata(&metadata);
+
+        assert!(text.ends_with(&network.serialize()));
+        assert_eq!(NeuralNetwork::deserialize(&text), network);
+        assert_eq!(NeuralNetwork::deserialize_binary(&binary), network);
+        assert_eq!(NeuralNetwork::deserialize_metadata(&text).unwrap(), metadata);
+        assert_eq!(NeuralNetwork::deserialize_binary_metadata(&binary).unwrap(), metadata);
+        let header_size = u32::from_le_bytes(binary[8..12].try_into().unwrap()) as usize;
+        assert_eq!(header_size % 4, 0);
+        assert_eq!(NeuralNetwork::deserialize_binary_metadata(&binary[..header_size]).unwrap(), metadata);
+        assert_eq!(NeuralNetwork::deserialize_metadata(&network.serialize()).unwrap(), Metadata::default());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let network = NeuralNetwork::new(&[5, 7, 10, 10]);
+
+        let json = serde_json::to_string(&network).unwrap();
+        let mut deserialized: NeuralNetwork = serde_json::from_str(&json).unwrap();
+
+        let bits = |x: &NeuralNetwork| x.parameters().iter().map(|x| x.to_bits()).collect::<Vec<u32>>();
+        assert_eq!(bits(&deserialized), bits(&network));
+        assert_eq!(deserialized.layers(), network.layers());
+        assert_eq!(deserialized.process_mutable(&[0.1, 0.2, 0.3, 0.4, 0.5]), network.process(&[0.1, 0.2, 0.3, 0.4, 0.5]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_rejects_wrong_shapes() {
+        let json = r#"{"weights": [[[0.1, 0.2], [0.3]]], "biases": [[0.5, 0.6]]}"#;
+
+        let result: Result<NeuralNetwork, _> = serde_json::from_str(json);
+
+        assert!(result.unwrap_err().to_string().contains("layer 1 weights: expected shape [2, 2], found [2, 1]"));
+    }
+
     #[test]
     fn test_deserialize_ignores_non_numeric_values() {
-        let serialized = "\nlayers\n1 1 1\n\nlayer 1\n0.99\n0.33\n\noutput layer\n0.13\n3.14\n\nthis should be ignored\n";
+        let serialized = "\nlayers\n1 1 1\n\nlayer 1\n0.99\n0.33\n\noutput layer\n0.13\n3.25\n\nthis should be ignored\n";
 
         let deserialized = NeuralNetwork::deserialize(serialized);
         let expected = NeuralNetwork {
             weights: vec![vec![vec![0.99]], vec![vec![0.13]]],
-            biases: vec![vec![0.33], vec![3.14]],
+            biases: vec![vec![0.33], vec![3.25]],
             pre_activations: vec![],
             activations: vec![],
         };
@@ -338,6 +819,136 @@ mod test {
         assert_eq!(deserialized, expected);
     }
 
+    #[test]
+    fn test_soft_targets() {
+        let network = get_network();
+
+        let hard = network.process(&[0.1, 0.8]);
+        let same = network.soft_targets(&[0.1, 0.8], 1.0);
+        let soft = network.soft_targets(&[0.1, 0.8], 4.0);
+
+        assert!((same[0] - hard[0]).abs() < 0.0001);
+        assert!(soft[0] > 0.5);
+        assert!(soft[0] < hard[0]);
+    }
+
+    #[test]
+    fn test_input_gradient() {
+        let network = get_network();
+        let input = [0.1, 0.8];
+        let epsilon = 0.001;
+
+        let gradient = network.input_gradient(&input, 0);
+
+        // compare with the numerical derivative
+        for i in 0..input.len() {
+            let mut plus = input;
+            let mut minus = input;
+            plus[i] += epsilon;
+            minus[i] -= epsilon;
+            let expected = (network.process(&plus)[0] - network.process(&minus)[0]) / (2.0 * epsilon);
+            assert!((gradient[i] - expected).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_missing_header() {
+        let result = NeuralNetwork::try_deserialize("input layer:\n3.456\n2.123\n");
+
+        assert!(matches!(result, Err(ModelError::MissingHeader)));
+    }
+
+    #[test]
+    fn test_deserialize_bad_layer_size() {
+        let result = NeuralNetwork::try_deserialize("2 -3 1\n0.1\n");
+
+        assert!(matches!(result, Err(ModelError::BadLayerSize { layer: 1, value }) if value == "-3"));
+    }
+
+    #[test]
+    fn test_deserialize_huge_layer_sizes() {
+        let text = NeuralNetwork::try_deserialize("4294967295 4294967295 4294967295\n0.1\n");
+        let mut binary = BINARY_MAGIC.to_vec();
+        binary.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+        binary.extend_from_slice(&0u16.to_le_bytes());
+        for x in [28, 3, u32::MAX, u32::MAX, u32::MAX] {
+            binary.extend_from_slice(&x.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0; 16]);
+
+        assert!(matches!(text, Err(ModelError::BadLayerSize { layer: 2, .. })));
+        assert!(matches!(NeuralNetwork::try_deserialize_strict("4294967295 4294967295 4294967295\n"), Err(ModelError::BadLayerSize { layer: 2, .. })));
+        assert!(matches!(NeuralNetwork::try_deserialize_binary(&binary), Err(ModelError::BadLayerSize { layer: 2, .. })));
+    }
+
+    #[test]
+    fn test_deserialize_unexpected_eof() {
+        let serialized = get_network().serialize();
+        let truncated: Vec<&str> = serialized.lines().take(10).collect();
+
+        let result = NeuralNetwork::try_deserialize(&truncated.join("\n"));
+
+        // 10 lines are the header, "layer 1", 8 values
+        assert!(matches!(result, Err(ModelError::UnexpectedEof { expected: 20, actual: 8 })));
+    }
+
+    #[test]
+    fn test_deserialize_unparsable_value() {
+        let result = NeuralNetwork::try_deserialize("1 1\nlayer 1\n0.5\n0,25\n");
+
+        assert!(matches!(result, Err(ModelError::UnparsableValue { line: 4, value }) if value == "0,25"));
+    }
+
+    #[test]
+    fn test_deserialize_binary_unexpected_eof() {
+        let serialized = get_network().serialize_binary();
+
+        let result = NeuralNetwork::try_deserialize_binary(&serialized[..serialized.len() - 10]);
+
+        assert!(matches!(result, Err(ModelError::UnexpectedEof { expected: 20, actual: 18 })));
+    }
+
+    #[test]
+    fn test_deserialize_checksum_mismatch() {
+        let serialized = get_network().serialize().replace("\n0.7\n", "\n0.75\n");
+        let mut binary = get_network().serialize_binary();
+        binary[40] ^= 1;
+
+        let result = NeuralNetwork::try_deserialize(&serialized);
+        let binary_result = NeuralNetwork::try_deserialize_binary(&binary);
+
+        assert!(matches!(result, Err(ModelError::ChecksumMismatch { expected: 0xb123a352, .. })));
+        assert!(matches!(binary_result, Err(ModelError::ChecksumMismatch { expected: 0xb123a352, .. })));
+    }
+
+    #[test]
+    fn test_deserialize_strict_rejects_trailing_data() {
+        let serialized = get_network().serialize() + "\n0.5\n";
+        let mut binary = get_network().serialize_binary();
+        binary.extend_from_slice(&[0, 0]);
+
+        assert_eq!(NeuralNetwork::try_deserialize(&serialized).unwrap(), get_network());
+        assert_eq!(NeuralNetwork::try_deserialize_binary(&binary).unwrap(), get_network());
+        assert!(matches!(NeuralNetwork::try_deserialize_strict(&serialized), Err(ModelError::TrailingLine { line: 26, .. })));
+        assert!(matches!(NeuralNetwork::try_deserialize_binary_strict(&binary), Err(ModelError::TrailingBytes { count: 2 })));
+    }
+
+    #[test]
+    fn test_deserialize_strict_validates_sections() {
+        let serialized = get_network().serialize();
+        let missing_value = serialized.replace("0.7\n", "");
+        let wrong_marker = serialized.replace("layer 2", "layer 3");
+        let label = serialized.replace("layer 3\n", "layer 3\noutput layer\n");
+        let with_metadata = get_network().serialize_with_metadata(&Metadata::new()).replace("\n", "\n\n");
+
+        assert!(matches!(NeuralNetwork::try_deserialize_strict(&missing_value),
+            Err(ModelError::SectionSize { layer: 2, line: 12, inputs: 3, neurons: 2, actual: 7 })));
+        assert!(matches!(NeuralNetwork::try_deserialize_strict(&wrong_marker),
+            Err(ModelError::UnexpectedSection { line: 12, expected, actual }) if expected == "layer 2" && actual == "layer 3"));
+        assert!(matches!(NeuralNetwork::try_deserialize_strict(&label), Err(ModelError::UnparsableValue { line: 22, .. })));
+        assert_eq!(NeuralNetwork::try_deserialize_strict(&with_metadata).unwrap(), get_network());
+    }
+
     //todo add at least invoking of "training_step", so it doesn't crash on different values
     //todo add a test for learning_step, calculate values manually
     #[test]
@@ -399,4 +1010,36 @@ mod test {
         println!("new weights: {:?}", network.weights);
         println!("new biases: {:?}", network.biases);
     }
+
+    #[test]
+    fn test_training_batch() {
+        let (input1, target1) = ([0.1, 0.8], [0.9]);
+        let (input2, target2) = ([0.5, 0.2], [0.1]);
+        let mut single = get_network();
+        let mut batch = get_network();
+        let mut expected = get_network();
+        let mut first = get_network();
+        let mut second = get_network();
+
+        let loss = single.training_step(&input1, &target1, 0.5);
+        let outputs = batch.training_batch(&[(&input1, &target1), (&input2, &target2)], 0.5);
+        // the mean of two separate steps from the same network
+        first.training_step(&input1, &target1, 0.5);
+        second.training_step(&input2, &target2, 0.5);
+        for layer in 0..expected.weights.len() {
+            for i in 0..expected.weights[layer].len() {
+                for j in 0..expected.weights[layer][i].len() {
+                    expected.weights[layer][i][j] = (first.weights[layer][i][j] + second.weights[layer][i][j]) / 2.0;
+                }
+                expected.biases[layer][i] = (first.biases[layer][i] + second.biases[layer][i]) / 2.0;
+            }
+        }
+
+        assert!((loss - 0.5 * (get_network().process(&input1)[0] - 0.9).powi(2)).abs() < 1e-6);
+        assert_eq!(outputs, vec![get_network().process(&input1), get_network().process(&input2)]);
+        assert!(batch.parameters().iter().zip(expected.parameters()).all(|(a, b)| (a - b).abs() < 1e-6));
+        // an empty batch doesn't change the network
+        assert!(batch.training_batch(&[], 0.5).is_empty());
+        assert!(batch.parameters().iter().zip(expected.parameters()).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
 }
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use crate::checksum::crc32;
+use crate::error::ModelError;
+use crate::neural_network::NeuralNetwork;
+use crate::safetensors::{f32_size, from_tensors};
+
+// .npy: magic, version, header length (u16 in version 1, u32 in versions 2 and 3), a python dict literal
+// like {'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), } padded with spaces and ending with '\n',
+// then the raw values; .npz is a zip of .npy files, one per array,
+// the arrays are named like the safetensors tensors, np.load("network.npz")["layers.0.weight"]
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+// the header is padded so the data starts at a multiple of 64 bytes
+const NPY_ALIGNMENT: usize = 64;
+
+const ZIP_LOCAL_FILE: u32 = 0x04034b50;
+const ZIP_CENTRAL_DIRECTORY: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+// 1980-01-01, the earliest date zip can store
+const ZIP_DATE: u16 = 0x0021;
+
+pub fn to_npy(shape: &[usize], values: &[f32]) -> Vec<u8> {
+    let shape = match shape {
+        [size] => format!("({size},)"),
+        _ => format!("({})", shape.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {shape}, }}").into_bytes();
+    let length = (NPY_MAGIC.len() + 4 + header.len() + 1).next_multiple_of(NPY_ALIGNMENT);
+    header.resize(length - NPY_MAGIC.len() - 4 - 1, b' ');
+    header.push(b'\n');
+
+    let mut result: Vec<u8> = Vec::new();
+    result.extend_from_slice(NPY_MAGIC);
+    result.extend_from_slice(&[1, 0]);
+    result.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    result.extend_from_slice(&header);
+    for value in values {
+        result.extend_from_slice(&value.to_le_bytes());
+    }
+    result
+}
+
+// only little-endian f32 arrays in C order are supported, which is what numpy writes for float32 by default
+pub fn from_npy(input: &[u8], name: &str) -> Result<(Vec<usize>, Vec<f32>), ModelError> {
+    let invalid = |message: &str| ModelError::InvalidHeader(format!("{name}: {message}"));
+    if !input.starts_with(NPY_MAGIC) || input.len() < 10 {
+        return Err(invalid("not a .npy file"));
+    }
+    let (header_length, header_start) = match input[6] {
+        1 => (u16::from_le_bytes(input[8..10].try_into().unwrap()) as usize, 10),
+        2 | 3 if input.len() >= 12 => (u32::from_le_bytes(input[8..12].try_into().unwrap()) as usize, 12),
+        version => return Err(ModelError::UnsupportedVersion(version as u16)),
+    };
+    let header = input.get(header_start..header_start + header_length).ok_or(invalid("header longer than the file"))?;
+    let header = std::str::from_utf8(header).map_err(|_| invalid("header is not UTF-8"))?;
+
+    let dtype = header_value(header, "descr").ok_or(invalid("missing descr"))?;
+    let dtype = dtype.trim_matches(|c| c == '\'' || c == '"');
+    // '|' and '=' mean the native order, which is little-endian for f32 on every platform numpy runs on
+    if !matches!(dtype, "<f4" | "=f4" | "|f4") {
+        return Err(ModelError::UnsupportedDtype { name: name.to_string(), dtype: dtype.to_string() });
+    }
+    if header_value(header, "fortran_order").ok_or(invalid("missing fortran_order"))? != "False" {
+        return Err(invalid("fortran order isn't supported, save np.ascontiguousarray(array)"));
+    }
+    let shape = header_value(header, "shape").ok_or(invalid("missing shape"))?;
+    let shape: Vec<usize> = shape.trim_start_matches('(').trim_end_matches(')')
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse().map_err(|_| invalid("bad shape")))
+        .collect::<Result<_, _>>()?;
+
+    let data = &input[header_start + header_length..];
+    if Some(data.len()) != f32_size(&shape) {
+        return Err(invalid("data size doesn't match the shape"));
+    }
+    let values = data.chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect();
+    Ok((shape, values))
+}
+
+// the value of a key in the header dict, tuples are returned with the parentheses
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{key}'")).or_else(|| header.find(&format!("\"{key}\"")))? + key.len() + 2;
+    let value = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    let end = match value.starts_with('(') {
+        true => value.find(')')? + 1,
+        false => value.find([',', '}'])?,
+    };
+    Some(value[..end].trim())
+}
+
+pub fn to_npz(network: &NeuralNetwork) -> Vec<u8> {
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for layer in 0..network.weights.len() {
+        let neurons = network.biases[layer].len();
+        let inputs = network.weights[layer][0].len();
+        let weights: Vec<f32> = network.weights[layer].iter().flatten().copied().collect();
+        files.push((format!("layers.{layer}.weight.npy"), to_npy(&[neurons, inputs], &weights)));
+        files.push((format!("layers.{layer}.bias.npy"), to_npy(&[neurons], &network.biases[layer])));
+    }
+    zip_stored(&files)
+}
+
+pub fn from_npz(input: &[u8]) -> Result<NeuralNetwork, ModelError> {
+    let files = unzip_stored(input)?;
+    let names = files.keys().map(|name| name.strip_suffix(".npy").unwrap_or(name));
+    from_tensors(names, |name| {
+        files.get(&format!("{name}.npy"))
+            .map(|file| from_npy(file, name))
+            .transpose()
+    })
+}
+
+// a zip without compression, like np.savez writes
+fn zip_stored(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+    let mut central_directory: Vec<u8> = Vec::new();
+    for (name, contents) in files {
+        let offset = result.len() as u32;
+        let crc = crc32(contents);
+        // version needed, flags, method (stored), time, date, crc, compressed and uncompressed size, name length, extra length
+        let mut fields: Vec<u8> = Vec::new();
+        fields.extend_from_slice(&20u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&ZIP_DATE.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        result.extend_from_slice(&ZIP_LOCAL_FILE.to_le_bytes());
+        result.extend_from_slice(&fields);
+        result.extend_from_slice(name.as_bytes());
+        result.extend_from_slice(contents);
+
+        // version made by, the local fields, comment length, disk, internal and external attributes, offset
+        central_directory.extend_from_slice(&ZIP_CENTRAL_DIRECTORY.to_le_bytes());
+        central_directory.extend_from_slice(&20u16.to_le_bytes());
+        central_directory.extend_from_slice(&fields);
+        central_directory.extend_from_slice(&[0; 10]);
+        central_directory.extend_from_slice(&offset.to_le_bytes());
+        central_directory.extend_from_slice(name.as_bytes());
+    }
+    let central_directory_offset = result.len() as u32;
+    result.extend_from_slice(&central_directory);
+
+    // disk numbers, entries on this disk and in total, central directory size and offset, comment length
+    result.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    result.extend_from_slice(&[0; 4]);
+    result.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    result.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    result.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+    result.extend_from_slice(&central_directory_offset.to_le_bytes());
+    result.extend_from_slice(&0u16.to_le_bytes());
+    result
+}
+
+// reads the files listed in the central directory, np.savez writes zip64 extra fields even for small files
+fn unzip_stored(input: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, ModelError> {
+    let invalid = |message: &str| ModelError::InvalidHeader(format!("zip: {message}"));
+    // positions come from the file, so none of them is trusted to stay inside it or to not overflow
+    let u16_at = |position: usize| input.get(position..position.checked_add(2)?).map(|x| u16::from_le_bytes(x.try_into().unwrap()));
+    let u32_at = |position: usize| input.get(position..position.checked_add(4)?).map(|x| u32::from_le_bytes(x.try_into().unwrap()));
+    let u64_at = |position: usize| input.get(position..position.checked_add(8)?).map(|x| u64::from_le_bytes(x.try_into().unwrap()));
+
+    // the end of central directory record is the last 22 bytes, unless there is a comment after it
+    let end = (0..input.len().saturating_sub(21)).rev()
+        .find(|&i| u32_at(i) == Some(ZIP_END_OF_CENTRAL_DIRECTORY))
+        .ok_or(invalid("missing end of central directory"))?;
+    let entries = u16_at(end + 10).ok_or(invalid("truncated end of central directory"))?;
+    let mut position = u32_at(end + 16).ok_or(invalid("truncated end of central directory"))? as usize;
+
+    let mut result = BTreeMap::new();
+    for _ in 0..entries {
+        if u32_at(position) != Some(ZIP_CENTRAL_DIRECTORY) {
+            return Err(invalid("bad central directory entry"));
+        }
+        let truncated = || invalid("truncated central directory");
+        let method = u16_at(position + 10).ok_or_else(truncated)?;
+        let crc = u32_at(position + 16).ok_or_else(truncated)?;
+        let mut size = u32_at(position + 20).ok_or_else(truncated)? as u64;
+        let mut uncompressed_size = u32_at(position + 24).ok_or_else(truncated)? as u64;
+        let name_length = u16_at(position + 28).ok_or_else(truncated)? as usize;
+        let extra_length = u16_at(position + 30).ok_or_else(truncated)? as usize;
+        let comment_length = u16_at(position + 32).ok_or_else(truncated)? as usize;
+        let mut offset = u32_at(position + 42).ok_or_else(truncated)? as u64;
+        let name = input.get(position + 46..position + 46 + name_length).ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).to_string();
+
+        // zip64 extra field: only the values that are 0xFFFFFFFF in the entry are present, in this order
+        let mut extra = position + 46 + name_length;
+        while extra + 4 <= position + 46 + name_length + extra_length {
+            let id = u16_at(extra).ok_or_else(truncated)?;
+            let length = u16_at(extra + 2).ok_or_else(truncated)? as usize;
+            if id == ZIP64_EXTRA {
+                let mut field = extra + 4;
+                for value in [&mut uncompressed_size, &mut size, &mut offset] {
+                    if *value == 0xFFFFFFFF {
+                        *value = u64_at(field).ok_or_else(truncated)?;
+                        field += 8;
+                    }
+                }
+            }
+            extra += 4 + length;
+        }
+        position += 46 + name_length + extra_length + comment_length;
+
+        if method != 0 {
+            return Err(invalid(&format!("{name} is compressed, only stored files are supported, use np.savez")));
+        }
+        if size != uncompressed_size {
+            return Err(invalid(&format!("{name}: the sizes of a stored file differ")));
+        }
+        let offset = usize::try_from(offset).map_err(|_| truncated())?;
+        if u32_at(offset) != Some(ZIP_LOCAL_FILE) {
+            return Err(invalid(&format!("{name}: bad local file header")));
+        }
+        let local_name_length = offset.checked_add(26).and_then(u16_at).ok_or_else(truncated)? as usize;
+        let local_extra_length = offset.checked_add(28).and_then(u16_at).ok_or_else(truncated)? as usize;
+        let start = offset.checked_add(30 + local_name_length + local_extra_length).ok_or_else(truncated)?;
+        let contents = usize::try_from(size).ok()
+            .and_then(|size| start.checked_add(size))
+            .and_then(|end| input.get(start..end))
+            .ok_or(invalid(&format!("{name}: file longer than the zip")))?;
+
+        let actual = crc32(contents);
+        if actual != crc {
+            return Err(ModelError::ChecksumMismatch { expected: crc, actual });
+        }
+        result.insert(name, contents.to_vec());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ModelError;
+    use crate::neural_network::NeuralNetwork;
+    use crate::numpy::{from_npy, from_npz, to_npy, to_npz};
+
+    #[test]
+    fn test_npy_header() {
+        let npy = to_npy(&[2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let header = std::str::from_utf8(&npy[10..128]).unwrap();
+
+        assert_eq!(&npy[0..10], b"\x93NUMPY\x01\x00\x76\x00");
+        assert_eq!(header.trim_end(), "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }");
+        assert!(header.ends_with('\n'));
+        assert_eq!(npy.len(), 128 + 6 * 4);
+        assert_eq!(from_npy(&npy, "a").unwrap(), (vec![2, 3], vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        assert_eq!(from_npy(&to_npy(&[2], &[1.0, 2.0]), "a").unwrap(), (vec![2], vec![1.0, 2.0]));
+    }
+
+    #[test]
+    fn test_npz_round_trip() {
+        let network = NeuralNetwork::new(&[5, 7, 10, 10]);
+
+        let npz = to_npz(&network);
+        let deserialized = from_npz(&npz).unwrap();
+
+        assert_eq!(&npz[0..4], b"PK\x03\x04");
+        assert_eq!(deserialized, network);
+    }
+
+    #[test]
+    fn test_npy_validation() {
+        let mut float64 = to_npy(&[2], &[1.0, 2.0]);
+        let start = float64.windows(3).position(|x| x == b"<f4").unwrap();
+        float64[start..start + 3].copy_from_slice(b"<f8");
+        let mut fortran = to_npy(&[1, 2], &[1.0, 2.0]);
+        let start = fortran.windows(5).position(|x| x == b"False").unwrap();
+        fortran.splice(start..start + 5, b"True ".iter().copied());
+
+        assert!(matches!(from_npy(&float64, "a"), Err(ModelError::UnsupportedDtype { dtype, .. }) if dtype == "<f8"));
+        assert!(matches!(from_npy(&fortran, "a"), Err(ModelError::InvalidHeader(_))));
+        assert!(matches!(from_npy(&to_npy(&[3], &[1.0, 2.0]), "a"), Err(ModelError::InvalidHeader(_))));
+        assert!(matches!(from_npy(&to_npy(&[1 << 62, 4], &[]), "a"), Err(ModelError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_npz_validation() {
+        let network = NeuralNetwork::new(&[2, 3, 1]);
+        let mut corrupted = to_npz(&network);
+        let last_value = corrupted.windows(4).position(|x| x == b"PK\x01\x02").unwrap() - 1;
+        corrupted[last_value] ^= 1;
+
+        assert!(matches!(from_npz(&corrupted), Err(ModelError::ChecksumMismatch { .. })));
+        assert!(matches!(from_npz(b"not a zip"), Err(ModelError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_npz_zip64_offset_outside_the_file() {
+        let mut npz = to_npz(&NeuralNetwork::new(&[2, 1]));
+        let entry = npz.windows(4).position(|x| x == b"PK\x01\x02").unwrap();
+        let name_length = u16::from_le_bytes(npz[entry + 28..entry + 30].try_into().unwrap()) as usize;
+        // the offset is replaced by a zip64 extra field with the largest offset
+        npz[entry + 30..entry + 32].copy_from_slice(&12u16.to_le_bytes());
+        npz[entry + 42..entry + 46].copy_from_slice(&u32::MAX.to_le_bytes());
+        let extra = [&1u16.to_le_bytes()[..], &8u16.to_le_bytes(), &u64::MAX.to_le_bytes()].concat();
+        npz.splice(entry + 46 + name_length..entry + 46 + name_length, extra);
+
+        assert!(matches!(from_npz(&npz), Err(ModelError::InvalidHeader(_))));
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use crate::error::ModelError;
+use crate::neural_network::NeuralNetwork;
+
+// safetensors layout: header length u64 little-endian, JSON header, raw little-endian tensor data;
+// the header maps tensor names to {"dtype", "shape", "data_offsets"}, the offsets are relative to the data,
+// every layer has "layers.{i}.weight" with shape [neurons, inputs] and "layers.{i}.bias" with shape [neurons]
+
+pub fn to_safetensors(network: &NeuralNetwork) -> Vec<u8> {
+    let mut entries: Vec<String> = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    for layer in 0..network.weights.len() {
+        let neurons = network.biases[layer].len();
+        let inputs = network.weights[layer][0].len();
+
+        let begin = data.len();
+        for value in network.weights[layer].iter().flatten() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        entries.push(tensor_entry(&format!("layers.{layer}.weight"), &[neurons, inputs], begin, data.len()));
+
+        let begin = data.len();
+        for value in &network.biases[layer] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        entries.push(tensor_entry(&format!("layers.{layer}.bias"), &[neurons], begin, data.len()));
+    }
+
+    // the data is aligned to 8 bytes by padding the header with spaces
+    let mut header = format!("{{{}}}", entries.join(",")).into_bytes();
+    header.resize(header.len().next_multiple_of(8), b' ');
+
+    let mut result: Vec<u8> = Vec::new();
+    result.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    result.extend_from_slice(&header);
+    result.extend_from_slice(&data);
+    result
+}
+
+fn tensor_entry(name: &str, shape: &[usize], begin: usize, end: usize) -> String {
+    let shape = shape.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",");
+    format!("\"{name}\":{{\"dtype\":\"F32\",\"shape\":[{shape}],\"data_offsets\":[{begin},{end}]}}")
+}
+
+pub fn from_safetensors(input: &[u8]) -> Result<NeuralNetwork, ModelError> {
+    let invalid = |message: &str| ModelError::InvalidHeader(message.to_string());
+    let header_length = input.get(0..8).ok_or(invalid("missing header length"))?;
+    let header_length = u64::from_le_bytes(header_length.try_into().unwrap()) as usize;
+    let header = input.get(8..8usize.saturating_add(header_length)).ok_or(invalid("header longer than the file"))?;
+    let header = std::str::from_utf8(header).map_err(|_| invalid("header is not UTF-8"))?;
+    let data = &input[8 + header_length..];
+
+    let tensors = match Json::parse(header)? {
+        Json::Object(tensors) => tensors,
+        _ => return Err(invalid("header is not an object")),
+    };
+
+    let names = tensors.keys().filter(|&name| name != "__metadata__").map(String::as_str);
+    from_tensors(names, |name| tensors.get(name).map(|tensor| read_tensor(tensor, name, data)).transpose())
+}
+
+// builds the network from "layers.{i}.weight" and "layers.{i}.bias" tensors, read returns None for missing tensors;
+// the layers end at the first missing weight and every shape must match the previous layer,
+// names are all tensors of the file, one that isn't a layer, like one after a gap in the numbering, is an error
+pub(crate) fn from_tensors<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    mut read: impl FnMut(&str) -> Result<Option<(Vec<usize>, Vec<f32>)>, ModelError>,
+) -> Result<NeuralNetwork, ModelError> {
+    let mut weights: Vec<Vec<Vec<f32>>> = Vec::new();
+    let mut biases: Vec<Vec<f32>> = Vec::new();
+    let mut inputs = None;
+    for layer in 0.. {
+        let weight_name = format!("layers.{layer}.weight");
+        let bias_name = format!("layers.{layer}.bias");
+        let (weight_shape, weight_values) = match read(&weight_name)? {
+            Some(weight) => weight,
+            None if layer > 0 => break,
+            None => return Err(ModelError::MissingTensor(weight_name)),
+        };
+        let (bias_shape, bias_values) = read(&bias_name)?.ok_or(ModelError::MissingTensor(bias_name.clone()))?;
+
+        let neurons = bias_shape.first().copied().unwrap_or(0);
+        let expected = vec![neurons, inputs.unwrap_or(weight_shape.get(1).copied().unwrap_or(0))];
+        if weight_shape != expected {
+            return Err(ModelError::ShapeMismatch { name: weight_name, expected, actual: weight_shape });
+        }
+        if bias_shape.len() != 1 {
+            return Err(ModelError::ShapeMismatch { name: bias_name, expected: vec![neurons], actual: bias_shape });
+        }
+        weights.push(weight_values.chunks(expected[1].max(1)).map(|row| row.to_vec()).collect());
+        biases.push(bias_values);
+        inputs = Some(neurons);
+    }
+
+    let used: Vec<String> = (0..weights.len()).flat_map(|layer| [format!("layers.{layer}.weight"), format!("layers.{layer}.bias")]).collect();
+    if let Some(name) = names.into_iter().find(|&name| !used.iter().any(|x| x == name)) {
+        return Err(ModelError::InvalidHeader(format!("unused tensor {name}, the layers must be numbered from 0 without gaps")));
+    }
+    NeuralNetwork::try_from_parameters(weights, biases)
+}
+
+fn read_tensor(tensor: &Json, name: &str, data: &[u8]) -> Result<(Vec<usize>, Vec<f32>), ModelError> {
+    let invalid = |message: &str| ModelError::InvalidHeader(format!("{name}: {message}"));
+    let dtype = tensor.get("dtype").and_then(Json::as_str).ok_or(invalid("missing dtype"))?;
+    if dtype != "F32" {
+        return Err(ModelError::UnsupportedDtype { name: name.to_string(), dtype: dtype.to_string() });
+    }
+    let shape = tensor.get("shape").and_then(Json::as_usize_array).ok_or(invalid("missing shape"))?;
+    let offsets = tensor.get("data_offsets").and_then(Json::as_usize_array).ok_or(invalid("missing data offsets"))?;
+    if offsets.len() != 2 || offsets[0] > offsets[1] || offsets[1] > data.len() {
+        return Err(invalid("data offsets out of the file"));
+    }
+    let bytes = &data[offsets[0]..offsets[1]];
+    if Some(bytes.len()) != f32_size(&shape) {
+        return Err(invalid("data size doesn't match the shape"));
+    }
+    let values = bytes.chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect();
+    Ok((shape, values))
+}
+
+// bytes of an f32 tensor with that shape, None if it doesn't fit in usize
+pub(crate) fn f32_size(shape: &[usize]) -> Option<usize> {
+    shape.iter().try_fold(4usize, |size, &x| size.checked_mul(x))
+}
+
+// deeper headers are rejected, every level is a recursion, a real header has 3
+const MAX_JSON_DEPTH: usize = 64;
+
+// only as much JSON as the safetensors header needs, numbers are kept as text
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn parse(input: &str) -> Result<Json, ModelError> {
+        let mut parser = JsonParser { input: input.as_bytes(), position: 0, depth: 0 };
+        let result = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.input.len() {
+            return Err(parser.error("unexpected data after the header"));
+        }
+        Ok(result)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(values) => values.get(key),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn as_usize_array(&self) -> Option<Vec<usize>> {
+        match self {
+            Json::Array(values) => values.iter()
+                .map(|x| match x {
+                    Json::Number(number) => number.parse().ok(),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    input: &'a [u8],
+    position: usize,
+    // objects and arrays the parser is in
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, message: &str) -> ModelError {
+        ModelError::InvalidHeader(format!("{message} at byte {}", self.position))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && self.input[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), ModelError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, ModelError> {
+        if !self.input[self.position..].starts_with(keyword.as_bytes()) {
+            return Err(self.error("unexpected value"));
+        }
+        self.position += keyword.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, ModelError> {
+        match self.peek() {
+            Some(b'{' | b'[') => {
+                if self.depth == MAX_JSON_DEPTH {
+                    return Err(self.error("header nested too deeply"));
+                }
+                self.depth += 1;
+                let result = if self.input[self.position] == b'{' { self.object() } else { self.array() };
+                self.depth -= 1;
+                result
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.position;
+                while self.position < self.input.len() && matches!(self.input[self.position], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+                    self.position += 1;
+                }
+                Ok(Json::Number(String::from_utf8_lossy(&self.input[start..self.position]).to_string()))
+            }
+            _ => Err(self.error("unexpected value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, ModelError> {
+        self.expect(b'{')?;
+        let mut result = BTreeMap::new();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(result));
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            result.insert(key, self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(result));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, ModelError> {
+        self.expect(b'[')?;
+        let mut result = Vec::new();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(result));
+        }
+        loop {
+            result.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(result));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ModelError> {
+        self.expect(b'"')?;
+        let mut result: Vec<u8> = Vec::new();
+        loop {
+            let byte = *self.input.get(self.position).ok_or(self.error("unterminated string"))?;
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.input.get(self.position).ok_or(self.error("unterminated string"))?;
+                    self.position += 1;
+                    match escaped {
+                        b'n' => result.push(b'\n'),
+                        b't' => result.push(b'\t'),
+                        b'r' => result.push(b'\r'),
+                        b'b' => result.push(0x08),
+                        b'f' => result.push(0x0C),
+                        b'u' => {
+                            let hex = self.input.get(self.position..self.position + 4).ok_or(self.error("bad escape"))?;
+                            let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16).map_err(|_| self.error("bad escape"))?;
+                            let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            result.extend_from_slice(c.to_string().as_bytes());
+                            self.position += 4;
+                        }
+                        other => result.push(other),
+                    }
+                }
+                other => result.push(other),
+            }
+        }
+        String::from_utf8(result).map_err(|_| self.error("string is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ModelError;
+    use crate::neural_network::NeuralNetwork;
+    use crate::safetensors::{from_safetensors, to_safetensors};
+
+    fn with_header(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut result = (header.len() as u64).to_le_bytes().to_vec();
+        result.extend_from_slice(header.as_bytes());
+        result.extend_from_slice(data);
+        result
+    }
+
+    #[test]
+    fn test_safetensors_round_trip() {
+        let network = NeuralNetwork::new(&[5, 7, 10, 10]);
+
+        let serialized = to_safetensors(&network);
+        let header_length = u64::from_le_bytes(serialized[0..8].try_into().unwrap()) as usize;
+        let deserialized = from_safetensors(&serialized).unwrap();
+
+        assert_eq!(header_length % 8, 0);
+        assert_eq!(serialized.len(), 8 + header_length + 4 * (5 * 7 + 7 + 7 * 10 + 10 + 10 * 10 + 10));
+        assert_eq!(deserialized, network);
+    }
+
+    #[test]
+    fn test_safetensors_header() {
+        let mut network = NeuralNetwork::new(&[2, 1]);
+        network.weights = vec![vec![vec![1.0, 2.0]]];
+        network.biases = vec![vec![3.0]];
+
+        let serialized = to_safetensors(&network);
+        let header = std::str::from_utf8(&serialized[8..serialized.len() - 12]).unwrap();
+
+        assert_eq!(header.trim_end(), "{\"layers.0.weight\":{\"dtype\":\"F32\",\"shape\":[1,2],\"data_offsets\":[0,8]},\
+        \"layers.0.bias\":{\"dtype\":\"F32\",\"shape\":[1],\"data_offsets\":[8,12]}}");
+    }
+
+    #[test]
+    fn test_safetensors_from_other_writer() {
+        let data: Vec<u8> = [0.5_f32, -0.5, 0.25, 1.0, 1.0, 2.0, 3.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let header = r#"{"__metadata__": {"format": "pt"},
+            "layers.1.weight": {"dtype": "F32", "shape": [1, 2], "data_offsets": [16, 24]},
+            "layers.1.bias": {"dtype": "F32", "shape": [1], "data_offsets": [24, 28]},
+            "layers.0.weight": {"dtype": "F32", "shape": [2, 1], "data_offsets": [0, 8]},
+            "layers.0.bias": {"dtype": "F32", "shape": [2], "data_offsets": [8, 16]}}"#;
+
+        let network = from_safetensors(&with_header(header, &data)).unwrap();
+
+        assert_eq!(network.weights, vec![vec![vec![0.5], vec![-0.5]], vec![vec![1.0, 2.0]]]);
+        assert_eq!(network.biases, vec![vec![0.25, 1.0], vec![3.0]]);
+    }
+
+    #[test]
+    fn test_safetensors_validates_shapes() {
+        let data = vec![0; 4 * 6];
+        let wrong_inputs = r#"{"layers.0.weight": {"dtype": "F32", "shape": [2, 1], "data_offsets": [0, 8]},
+            "layers.0.bias": {"dtype": "F32", "shape": [2], "data_offsets": [8, 16]},
+            "layers.1.weight": {"dtype": "F32", "shape": [1, 1], "data_offsets": [16, 20]},
+            "layers.1.bias": {"dtype": "F32", "shape": [1], "data_offsets": [20, 24]}}"#;
+        let wrong_dtype = r#"{"layers.0.weight": {"dtype": "F64", "shape": [1, 1], "data_offsets": [0, 8]},
+            "layers.0.bias": {"dtype": "F32", "shape": [1], "data_offsets": [8, 12]}}"#;
+        let missing_bias = r#"{"layers.0.weight": {"dtype": "F32", "shape": [1, 1], "data_offsets": [0, 4]}}"#;
+
+        assert!(matches!(from_safetensors(&with_header(wrong_inputs, &data)),
+            Err(ModelError::ShapeMismatch { name, expected, actual }) if name == "layers.1.weight" && expected == vec![1, 2] && actual == vec![1, 1]));
+        assert!(matches!(from_safetensors(&with_header(wrong_dtype, &data)), Err(ModelError::UnsupportedDtype { .. })));
+        assert!(matches!(from_safetensors(&with_header(missing_bias, &data)), Err(ModelError::MissingTensor(name)) if name == "layers.0.bias"));
+        assert!(matches!(from_safetensors(&with_header("{\"layers", &data)), Err(ModelError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_safetensors_rejects_unused_tensors() {
+        let data = vec![0; 4 * 4];
+        let gap = r#"{"layers.0.weight": {"dtype": "F32", "shape": [1, 1], "data_offsets": [0, 4]},
+            "layers.0.bias": {"dtype": "F32", "shape": [1], "data_offsets": [4, 8]},
+            "layers.2.weight": {"dtype": "F32", "shape": [1, 1], "data_offsets": [8, 12]},
+            "layers.2.bias": {"dtype": "F32", "shape": [1], "data_offsets": [12, 16]}}"#;
+
+        assert!(matches!(from_safetensors(&with_header(gap, &data)), Err(ModelError::InvalidHeader(message)) if message.contains("layers.2.bias")));
+    }
+
+    #[test]
+    fn test_safetensors_rejects_hostile_headers() {
+        let nested = "[".repeat(100_000);
+        let huge_shape = r#"{"layers.0.weight": {"dtype": "F32", "shape": [4611686018427387904, 4], "data_offsets": [0, 0]},
+            "layers.0.bias": {"dtype": "F32", "shape": [4], "data_offsets": [0, 16]}}"#;
+
+        assert!(matches!(from_safetensors(&with_header(&nested, &[])), Err(ModelError::InvalidHeader(message)) if message.contains("nested")));
+        assert!(matches!(from_safetensors(&with_header(huge_shape, &[0; 16])), Err(ModelError::InvalidHeader(_))));
+    }
+}
@@ -0,0 +1,42 @@
+use crate::neural_network::NeuralNetwork;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaliencyMethod {
+    // how much every pixel would change the output
+    Gradient,
+    // the gradient weighted by the pixel value, only the drawn strokes are highlighted
+    GradientTimesInput,
+}
+
+pub fn saliency(network: &NeuralNetwork, input: &[f32], output: usize, method: SaliencyMethod) -> Vec<f32> {
+    saliency_map(&network.input_gradient(input, output), input, method)
+}
+
+pub fn saliency_map(gradient: &[f32], input: &[f32], method: SaliencyMethod) -> Vec<f32> {
+    match method {
+        SaliencyMethod::Gradient => gradient.to_vec(),
+        SaliencyMethod::GradientTimesInput => gradient.iter().zip(input.iter()).map(|(g, x)| g * x).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::neural_network::NeuralNetwork;
+    use crate::saliency::{saliency, SaliencyMethod};
+
+    #[test]
+    fn test_saliency_signs() {
+        let mut network = NeuralNetwork::new(&[3, 1]);
+        network.weights = vec![vec![vec![2.0, -1.0, 0.5]]];
+        network.biases = vec![vec![0.0]];
+        let input = [0.5, 1.0, 0.0];
+
+        let gradient = saliency(&network, &input, 0, SaliencyMethod::Gradient);
+        let times_input = saliency(&network, &input, 0, SaliencyMethod::GradientTimesInput);
+
+        assert_eq!(gradient.len(), 3);
+        // sigmoid is increasing, so the gradient has the sign of the weight
+        assert!(gradient[0] > 0.0 && gradient[1] < 0.0 && gradient[2] > 0.0);
+        assert_eq!(times_input, vec![gradient[0] * 0.5, gradient[1], 0.0]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use crate::neural_network::NeuralNetwork;
+
+// weights with smaller absolute value are considered "near zero"
+pub const NEAR_ZERO: f32 = 0.001;
+// activations closer than that to 0 or 1 are considered saturated
+pub const SATURATION: f32 = 0.01;
+pub const HISTOGRAM_BINS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub min: f32,
+    pub max: f32,
+    // equal width bins between min and max
+    pub counts: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterStatistics {
+    pub count: usize,
+    pub mean: f32,
+    pub std: f32,
+    pub min: f32,
+    pub max: f32,
+    pub l2_norm: f32,
+    pub near_zero: f32,
+    pub histogram: Histogram,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerStatistics {
+    // 1 is the first layer after the input
+    pub layer: usize,
+    pub inputs: usize,
+    pub neurons: usize,
+    pub weights: ParameterStatistics,
+    pub biases: ParameterStatistics,
+    // per neuron, fraction of the samples for which the activation was saturated
+    pub saturation: Vec<f32>,
+    // neurons saturated for every sample, they don't learn anymore
+    pub dead_neurons: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkStatistics {
+    pub layers: Vec<LayerStatistics>,
+    pub samples: usize,
+}
+
+pub fn statistics(network: &NeuralNetwork, samples: &[Vec<f32>]) -> NetworkStatistics {
+    let mut saturated: Vec<Vec<u32>> = network.biases.iter().map(|b| vec![0; b.len()]).collect();
+    for sample in samples {
+        let activations = network.layer_activations(sample);
+        for (layer, layer_activations) in activations.iter().enumerate() {
+            for (neuron, &a) in layer_activations.iter().enumerate() {
+                if !(SATURATION..=1.0 - SATURATION).contains(&a) {
+                    saturated[layer][neuron] += 1;
+                }
+            }
+        }
+    }
+
+    let mut layers = Vec::new();
+    for (i, layer_saturated) in saturated.iter().enumerate() {
+        let weights: Vec<f32> = network.weights[i].iter().flatten().copied().collect();
+        let saturation: Vec<f32> = layer_saturated.iter()
+            .map(|&count| if samples.is_empty() { 0.0 } else { count as f32 / samples.len() as f32 })
+            .collect();
+        let dead_neurons = saturation.iter().enumerate()
+            .filter(|(_, s)| !samples.is_empty() && **s == 1.0)
+            .map(|(neuron, _)| neuron)
+            .collect();
+        layers.push(LayerStatistics {
+            layer: i + 1,
+            inputs: network.weights[i].first().map_or(0, |w| w.len()),
+            neurons: network.biases[i].len(),
+            weights: parameter_statistics(&weights),
+            biases: parameter_statistics(&network.biases[i]),
+            saturation,
+            dead_neurons,
+        });
+    }
+
+    NetworkStatistics { layers, samples: samples.len() }
+}
+
+pub fn parameter_statistics(values: &[f32]) -> ParameterStatistics {
+    let count = values.len();
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mean = values.iter().sum::<f32>() / count as f32;
+    let variance = values.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / count as f32;
+    let l2_norm = values.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let near_zero = values.iter().filter(|x| x.abs() < NEAR_ZERO).count() as f32 / count as f32;
+
+    let mut counts = vec![0; HISTOGRAM_BINS];
+    let width = (max - min) / HISTOGRAM_BINS as f32;
+    for x in values {
+        let bin = if width > 0.0 { ((x - min) / width) as usize } else { 0 };
+        counts[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    ParameterStatistics {
+        count, mean, std: variance.sqrt(), min, max, l2_norm, near_zero,
+        histogram: Histogram { min, max, counts },
+    }
+}
+
+impl fmt::Display for ParameterStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "count {}; mean {:.4}; std {:.4}; min {:.4}; max {:.4}; l2 {:.4}; near zero {:.2}%; histogram {:?}",
+               self.count, self.mean, self.std, self.min, self.max, self.l2_norm, self.near_zero * 100.0, self.histogram.counts)
+    }
+}
+
+impl fmt::Display for NetworkStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for layer in &self.layers {
+            writeln!(f, "layer {} ({} -> {})", layer.layer, layer.inputs, layer.neurons)?;
+            writeln!(f, "  weights: {}", layer.weights)?;
+            writeln!(f, "  biases: {}", layer.biases)?;
+            if self.samples > 0 {
+                let mean_saturation = layer.saturation.iter().sum::<f32>() / layer.saturation.len() as f32;
+                writeln!(f, "  saturation over {} samples: {:.2}%; dead neurons {} {:?}",
+                         self.samples, mean_saturation * 100.0, layer.dead_neurons.len(), layer.dead_neurons)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::neural_network::NeuralNetwork;
+    use crate::statistics::{parameter_statistics, statistics};
+
+    #[test]
+    fn test_parameter_statistics() {
+        let result = parameter_statistics(&[-1.0, 0.0, 1.0, 2.0]);
+
+        assert_eq!(result.count, 4);
+        assert_eq!(result.mean, 0.5);
+        assert!((result.std - 1.118034).abs() < 0.0001);
+        assert_eq!(result.min, -1.0);
+        assert_eq!(result.max, 2.0);
+        assert!((result.l2_norm - 6.0_f32.sqrt()).abs() < 0.0001);
+        assert_eq!(result.near_zero, 0.25);
+        assert_eq!(result.histogram.counts.iter().sum::<u32>(), 4);
+        assert_eq!(result.histogram.counts[0], 1);
+        assert_eq!(result.histogram.counts[9], 1);
+    }
+
+    #[test]
+    fn test_dead_neurons() {
+        let mut network = NeuralNetwork::new(&[2, 3]);
+        network.weights = vec![vec![vec![0.1, 0.1], vec![50.0, 50.0], vec![-50.0, -50.0]]];
+        network.biases = vec![vec![0.0, 0.0, 0.0]];
+        let samples = vec![vec![0.5, 0.5], vec![1.0, 0.2]];
+
+        let result = statistics(&network, &samples);
+
+        assert_eq!(result.layers.len(), 1);
+        assert_eq!(result.layers[0].inputs, 2);
+        assert_eq!(result.layers[0].neurons, 3);
+        assert_eq!(result.layers[0].saturation, vec![0.0, 1.0, 1.0]);
+        assert_eq!(result.layers[0].dead_neurons, vec![1, 2]);
+    }
+}
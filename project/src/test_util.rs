@@ -0,0 +1,28 @@
+// files for the tests, named after the test and the process, so parallel tests and runs don't share them
+use std::fs;
+use crate::image::{save_training_data, HEIGHT, WIDTH};
+
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("{name}_{}", std::process::id())).display().to_string()
+}
+
+pub fn temp_file(name: &str, contents: &[u8]) -> String {
+    let path = temp_path(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// a dataset catalog where every digit is a different horizontal bar
+pub fn temp_dataset(name: &str, per_digit: u32) -> String {
+    let dataset = temp_path(name);
+    for digit in 0..10 {
+        fs::create_dir_all(format!("{dataset}/{digit}/{digit}")).unwrap();
+        for index in 0..per_digit {
+            let mut image = [0.0; WIDTH * HEIGHT];
+            let row = 2 * digit as usize + index as usize % 2;
+            image[row * WIDTH..(row + 1) * WIDTH].fill(1.0);
+            save_training_data(&dataset, digit, &image, index);
+        }
+    }
+    dataset
+}
@@ -0,0 +1,390 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::time::Duration;
+use rand::{Rng, SeedableRng};
+use rand::seq::IndexedRandom;
+use rand_chacha::ChaCha12Rng;
+use crate::metadata::Metadata;
+use crate::neural_network::NeuralNetwork;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopCondition {
+    Duration(Duration),
+    // number of steps, with batches every step uses batch_size samples
+    Iterations(u64),
+    // one epoch is as many samples as there are in the training dataset
+    Epochs(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    // a random digit and then a random image of it, some images are used more often than others and some never
+    Random,
+    // every epoch visits every image exactly once, in a new random order
+    Shuffled,
+}
+
+// how the learning rate changes during the training, the step counts from 0,
+// the rates are relative to the learning rate of the config unless their name says otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Constant,
+    // multiplied by factor every `every` steps
+    Step { every: u64, factor: f32 },
+    // multiplied by decay every step
+    Exponential { decay: f32 },
+    // from the learning rate down to min_rate along a half cosine, then back to the learning rate,
+    // every restart the period is multiplied by period_multiplier
+    Cosine { min_rate: f32, period: u64, period_multiplier: u64 },
+    // grows linearly from almost 0 to the rate of the inner schedule in `steps` steps,
+    // after that the inner schedule is used, starting at its step 0
+    Warmup { steps: u64, then: Box<Schedule> },
+    // linearly from the learning rate up to max_rate in half_period steps and back down, repeatedly
+    Triangular { max_rate: f32, half_period: u64 },
+    // every step a random factor of (factor, weight), for example mostly 1.0, sometimes 20.0 and rarely 200.0
+    Stochastic { factors: Vec<(f32, f32)> },
+}
+
+impl Schedule {
+    // only the stochastic schedule uses the rng
+    pub fn rate(&self, learning_rate: f32, step: u64, rng: &mut impl Rng) -> f32 {
+        match self {
+            Schedule::Constant => learning_rate,
+            Schedule::Step { every, factor } => learning_rate * factor.powf((step / (*every).max(1)) as f32),
+            Schedule::Exponential { decay } => learning_rate * decay.powf(step as f32),
+            Schedule::Cosine { min_rate, period, period_multiplier } => {
+                let (mut position, mut period) = (step, (*period).max(1));
+                if *period_multiplier <= 1 {
+                    position %= period;
+                }
+                // the periods grow geometrically, so this takes a logarithmic number of restarts
+                while position >= period {
+                    position -= period;
+                    period = period.saturating_mul((*period_multiplier).max(1));
+                }
+                min_rate + (learning_rate - min_rate) * (1.0 + (PI * position as f32 / period as f32).cos()) / 2.0
+            }
+            Schedule::Warmup { steps, then } => {
+                if step < *steps {
+                    then.rate(learning_rate, 0, rng) * (step + 1) as f32 / *steps as f32
+                } else {
+                    then.rate(learning_rate, step - steps, rng)
+                }
+            }
+            Schedule::Triangular { max_rate, half_period } => {
+                let half_period = (*half_period).max(1);
+                let position = step % (2 * half_period);
+                let distance = position.min(2 * half_period - position) as f32 / half_period as f32;
+                learning_rate + (max_rate - learning_rate) * distance
+            }
+            Schedule::Stochastic { factors } => factors.choose_weighted(rng, |(_, weight)| *weight)
+                .map_or(learning_rate, |(factor, _)| learning_rate * factor),
+        }
+    }
+}
+
+// the network is evaluated on the validation dataset every `interval` steps and after the last step
+#[derive(Debug, Clone, PartialEq)]
+pub struct EarlyStopping {
+    pub interval: u64,
+    // the training stops after that many evaluations without a better accuracy
+    pub patience: u64,
+    // smaller improvements of the accuracy don't count
+    pub min_delta: f32,
+    // the training ends with the best network instead of the last one
+    pub restore_best: bool,
+    // every new best network is saved to this path
+    pub save_best: Option<String>,
+}
+
+impl Default for EarlyStopping {
+    fn default() -> Self {
+        EarlyStopping { interval: 1000, patience: 10, min_delta: 0.0, restore_best: true, save_best: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingConfig {
+    // the base rate of the schedule
+    pub learning_rate: f32,
+    pub schedule: Schedule,
+    pub stop: StopCondition,
+    // samples per step, the gradients are averaged over the batch
+    pub batch_size: usize,
+    pub sampling: Sampling,
+    // {dataset}/{digit}/{digit}/*.png
+    pub training_dataset: String,
+    // the accuracy on it is measured after the training
+    pub validation_dataset: Option<String>,
+    // needs the validation dataset
+    pub early_stopping: Option<EarlyStopping>,
+    // progress is printed every log_interval steps
+    pub log_interval: u64,
+    // the same seed picks the same samples in the same order, None picks a random seed
+    pub seed: Option<u64>,
+    // saved to this path every checkpoint_interval steps, resume continues from it
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: u64,
+}
+
+impl Default for TrainingConfig {
+    // what learn did before it had a config
+    fn default() -> Self {
+        TrainingConfig {
+            learning_rate: 0.5,
+            schedule: Schedule::Constant,
+            stop: StopCondition::Duration(Duration::from_secs(60 * 5)),
+            batch_size: 1,
+            sampling: Sampling::Random,
+            training_dataset: "training_data".to_string(),
+            validation_dataset: None,
+            early_stopping: None,
+            log_interval: 500,
+            seed: None,
+            checkpoint: None,
+            checkpoint_interval: 10_000,
+        }
+    }
+}
+
+// loss and accuracy of the outputs during the epoch, before each step changed the network
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpochSummary {
+    pub epoch: u64,
+    pub samples: u64,
+    pub loss: f32,
+    pub accuracy: f32,
+    pub duration: Duration,
+}
+
+// the best validation accuracy so far, the network is kept only to restore it
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestNetwork {
+    pub iteration: u64,
+    pub accuracy: f32,
+    pub network: Option<NeuralNetwork>,
+    // evaluations since then
+    pub stale: u64,
+}
+
+// everything the training loop needs to continue exactly where it stopped, with the network it is a checkpoint,
+// sgd has no optimizer state and the schedule only depends on the iteration and the rng
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingState {
+    pub seed: u64,
+    pub rng: ChaCha12Rng,
+    pub iteration: u64,
+    pub samples: u64,
+    // training time before the current run, counts for the duration stop condition
+    pub elapsed: Duration,
+    pub loss: f32,
+    // losses since the last log
+    pub loss_sum: f32,
+    pub loss_steps: u64,
+    pub epochs: Vec<EpochSummary>,
+    // the unfinished epoch
+    pub epoch_samples: u64,
+    pub epoch_loss: f32,
+    pub epoch_correct: u64,
+    pub epoch_elapsed: Duration,
+    // shuffled sampling goes through the samples in this order, the next batch starts at position
+    pub order: Vec<usize>,
+    pub position: usize,
+    pub best: Option<BestNetwork>,
+}
+
+impl TrainingState {
+    pub fn new(seed: u64, training_samples: usize) -> Self {
+        TrainingState {
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            iteration: 0,
+            samples: 0,
+            elapsed: Duration::ZERO,
+            loss: 0.0,
+            loss_sum: 0.0,
+            loss_steps: 0,
+            epochs: Vec::new(),
+            epoch_samples: 0,
+            epoch_loss: 0.0,
+            epoch_correct: 0,
+            epoch_elapsed: Duration::ZERO,
+            order: (0..training_samples).collect(),
+            position: training_samples,
+            best: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingSummary {
+    pub config: TrainingConfig,
+    // the seed that was used, also when the config didn't have one
+    pub seed: u64,
+    pub iterations: u64,
+    pub samples: u64,
+    pub training_samples: u64,
+    pub duration: Duration,
+    // mean loss of the steps since the last log
+    pub loss: f32,
+    // only the finished epochs
+    pub epochs: Vec<EpochSummary>,
+    pub validation_samples: Option<u64>,
+    pub validation_accuracy: Option<f32>,
+    // with early stopping, when the best validation accuracy was reached
+    pub best_iteration: Option<u64>,
+    pub best_accuracy: Option<f32>,
+    // early stopping or a callback stopped the training before the stop condition was met
+    pub stopped_early: bool,
+}
+
+impl TrainingSummary {
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            learning_rate: Some(self.config.learning_rate),
+            training_duration: Some(self.duration),
+            iterations: Some(self.iterations),
+            dataset: Some(self.config.training_dataset.clone()),
+            training_samples: Some(self.training_samples),
+            validation_samples: self.validation_samples,
+            validation_accuracy: self.validation_accuracy,
+            ..Metadata::new()
+        }
+    }
+
+    // how many times the training dataset was used, also for random sampling
+    pub fn dataset_passes(&self) -> f32 {
+        self.samples as f32 / self.training_samples.max(1) as f32
+    }
+}
+
+impl fmt::Display for TrainingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "iterations: {}, samples: {}, epochs: {:.2}", self.iterations, self.samples, self.dataset_passes())?;
+        for epoch in &self.epochs {
+            writeln!(f, "epoch {}: loss {:.6}, accuracy {:.2}%, {:?}", epoch.epoch, epoch.loss, epoch.accuracy * 100.0, epoch.duration)?;
+        }
+        writeln!(f, "duration: {:?}, seed: {}", self.duration, self.seed)?;
+        write!(f, "loss: {:.6}", self.loss)?;
+        if let Some(accuracy) = self.validation_accuracy {
+            write!(f, "\nvalidation accuracy: {:.2}% of {}", accuracy * 100.0, self.validation_samples.unwrap_or(0))?;
+        }
+        if let (Some(iteration), Some(accuracy)) = (self.best_iteration, self.best_accuracy) {
+            write!(f, "\nbest accuracy: {:.2}% at iteration {iteration}", accuracy * 100.0)?;
+            if self.stopped_early {
+                write!(f, ", stopped early")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// what the callbacks get after a step, the loss is the mean loss of the step, of the interval, of the epoch
+// or of the last steps for the end of the training
+#[derive(Debug, Clone, Copy)]
+pub struct TrainingProgress<'a> {
+    pub iteration: u64,
+    // finished epochs
+    pub epoch: u64,
+    // samples used so far, with shuffled sampling the batches at the end of the epochs may be smaller
+    pub samples: u64,
+    pub loss: f32,
+    pub learning_rate: f32,
+    pub network: &'a NeuralNetwork,
+}
+
+// hooks into the training loop, returning Break from any of them stops the training after the current step,
+// the other callbacks are still called for that step
+pub trait TrainingCallback {
+    fn on_step_end(&mut self, _progress: &TrainingProgress) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    // every log_interval steps of the config
+    fn on_interval(&mut self, _progress: &TrainingProgress) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    fn on_epoch_end(&mut self, _progress: &TrainingProgress, _epoch: &EpochSummary) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    // after the validation, there is nothing left to stop
+    fn on_training_end(&mut self, _progress: &TrainingProgress, _summary: &TrainingSummary) {}
+}
+
+// the progress lines learn prints
+pub struct ProgressLogger;
+
+impl TrainingCallback for ProgressLogger {
+    fn on_interval(&mut self, progress: &TrainingProgress) -> ControlFlow<()> {
+        println!("{}; iteration {}; loss {}; learning rate {};", chrono::Local::now(), progress.iteration, progress.loss, progress.learning_rate);
+        ControlFlow::Continue(())
+    }
+
+    fn on_epoch_end(&mut self, _progress: &TrainingProgress, epoch: &EpochSummary) -> ControlFlow<()> {
+        println!("{}; epoch {}; loss {}; accuracy {};", chrono::Local::now(), epoch.epoch, epoch.loss, epoch.accuracy);
+        ControlFlow::Continue(())
+    }
+
+    fn on_training_end(&mut self, progress: &TrainingProgress, _summary: &TrainingSummary) {
+        println!("{}; iteration {}; loss {}; learning rate {};", chrono::Local::now(), progress.iteration, progress.loss, progress.learning_rate);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::training::Schedule;
+
+    fn rates(schedule: &Schedule, steps: u64) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..steps).map(|step| schedule.rate(0.5, step, &mut rng)).collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_schedules() {
+        assert_eq!(rates(&Schedule::Constant, 3), vec![0.5, 0.5, 0.5]);
+        assert_close(&rates(&Schedule::Step { every: 2, factor: 0.1 }, 5), &[0.5, 0.5, 0.05, 0.05, 0.005]);
+        assert_close(&rates(&Schedule::Exponential { decay: 0.5 }, 3), &[0.5, 0.25, 0.125]);
+        // periods of 2 and 4 steps
+        assert_close(
+            &rates(&Schedule::Cosine { min_rate: 0.1, period: 2, period_multiplier: 2 }, 7),
+            &[0.5, 0.3, 0.5, 0.5 - 0.4 * (1.0 - 0.5f32.sqrt()) / 2.0, 0.3, 0.1 + 0.4 * (1.0 - 0.5f32.sqrt()) / 2.0, 0.5],
+        );
+        assert_close(
+            &rates(&Schedule::Warmup { steps: 4, then: Box::new(Schedule::Exponential { decay: 0.5 }) }, 6),
+            &[0.125, 0.25, 0.375, 0.5, 0.5, 0.25],
+        );
+        assert_close(&rates(&Schedule::Triangular { max_rate: 1.5, half_period: 2 }, 6), &[0.5, 1.0, 1.5, 1.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_cosine_late_steps() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let constant = Schedule::Cosine { min_rate: 0.1, period: 4, period_multiplier: 1 };
+        let growing = Schedule::Cosine { min_rate: 0.1, period: 4, period_multiplier: 3 };
+
+        // without a restart loop over every period, these return at once
+        assert_close(&[constant.rate(0.5, 4_000_000_000_000_002, &mut rng)], &[0.3]);
+        assert!((0.1..=0.5).contains(&growing.rate(0.5, u64::MAX, &mut rng)));
+    }
+
+    #[test]
+    fn test_stochastic_schedule() {
+        let rates = rates(&Schedule::Stochastic { factors: vec![(1.0, 0.9), (20.0, 0.1), (200.0, 0.0)] }, 1000);
+
+        assert!(rates.iter().all(|&rate| rate == 0.5 || rate == 10.0));
+        let high = rates.iter().filter(|&&rate| rate == 10.0).count();
+        assert!((50..150).contains(&high), "{high}");
+    }
+}